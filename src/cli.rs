@@ -0,0 +1,310 @@
+//! Command line handling
+
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::dump::{DumpFormat, Dumps};
+use noseburn::moostar::{
+    cell::Cell, EofPolicy, Limits, OutputMode, OverflowMode, Runner, TapeMode,
+};
+
+/// Summary of the accepted arguments, shown when they make no sense
+pub const USAGE: &str = "\
+Usage: noseburn [OPTIONS] <FILE>
+       noseburn run [OPTIONS] <FILE>
+
+The first form shows the program running step by step. The second one runs it at
+full speed without showing anything, with the standard input and output as its
+input and output, and exits with status 0 once it halts, 1 if it fails, 2 for bad
+arguments, 3 if it cannot be loaded and 4 if it goes past one of its limits.
+
+Options:
+  --entry <NAME>  Start from the function NAME instead of main or the top level code
+  --eof <POLICY>  What `,` does at the end of the input: zero, max, unchanged or wait (default)
+  --tape <MODE>   Shape of the ribbons: bounded (default), infinite in both directions,
+                  or circular:N for a ring of N cells
+  --cell <TYPE>   Values held by the cells: u8 (default), u16, u32, i8, i16 or i32
+  --overflow <MODE>
+                  What `+` and `-` do past the bounds of a cell: wrap (default), saturate or trap
+  --max-depth <N> Stop when calls go deeper than N (default 10000), or never with unlimited
+  --max-steps <N> Stop after running N steps
+  --timeout <SECONDS>
+                  Stop after running for SECONDS, which may be a fraction, with run only
+  --max-cells <N> Stop when writing to more than N cells of a ribbon
+  --decode <MODE> How to show the output: utf8 (default), latin1, dec or hex
+  --input <TEXT>  Add TEXT to the input of the program
+  --input-file <PATH>
+                  Add the contents of the file at PATH to the input of the program
+  --save-output <PATH>
+                  Write the output to the file at PATH once the program stops
+  --dump-data <PATH>
+                  Write the touched cells of the data ribbon to the file at PATH once
+                  the program stops
+  --dump-meta <PATH>
+                  Same for the meta ribbon
+  --dump-format <FORMAT>
+                  How to write the ribbons: hex (default), raw or json
+
+Input given on the standard input comes after the one given with options.";
+
+/// Somewhere the input of the program comes from
+pub enum InputSource {
+    Text(String),
+    File(PathBuf),
+}
+
+/// Integer types the cells can hold
+#[derive(Clone, Copy, Default)]
+pub enum CellType {
+    #[default]
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+}
+
+/// Everything the command line asked for
+pub struct Options {
+    /// Run without the visualizer
+    pub headless: bool,
+    /// Moostar file to load
+    pub path: PathBuf,
+    /// Function to start from
+    pub entry: Option<String>,
+    /// What to do when reading past the end of the input
+    pub eof: EofPolicy,
+    /// Shape of the ribbons
+    pub tape: TapeMode,
+    /// Values held by the cells
+    pub cell: CellType,
+    /// What to do past the bounds of a cell
+    pub overflow: OverflowMode,
+    /// Resources the program may use
+    pub limits: Limits,
+    /// How to show the output
+    pub output_mode: OutputMode,
+    /// Input of the program, in order
+    pub inputs: Vec<InputSource>,
+    /// Files to save the output and the ribbons to
+    pub dumps: Dumps,
+}
+
+impl Options {
+    /// Read the options from the arguments, without the name of the program
+    ///
+    /// Returns a message explaining what is wrong with them otherwise.
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.peekable();
+        let headless = args.next_if(|arg| arg == "run").is_some();
+        let mut path: Option<PathBuf> = None;
+        let mut entry: Option<String> = None;
+        let mut eof = EofPolicy::default();
+        let mut tape = TapeMode::default();
+        let mut cell = CellType::default();
+        let mut overflow = OverflowMode::default();
+        let mut limits = Limits::default();
+        let mut output_mode = OutputMode::default();
+        let mut inputs: Vec<InputSource> = Vec::new();
+        let mut dumps = Dumps::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--entry" => {
+                    entry = Some(args.next().ok_or("Missing function name after --entry")?);
+                }
+                "--eof" => {
+                    eof = match args.next().as_deref() {
+                        Some("zero" | "0") => EofPolicy::Zero,
+                        Some("max") => EofPolicy::Max,
+                        Some("unchanged") => EofPolicy::Unchanged,
+                        Some("wait") => EofPolicy::Wait,
+                        Some(other) => return Err(format!("Unknown EOF policy '{other}'")),
+                        None => return Err("Missing policy after --eof".to_string()),
+                    };
+                }
+                "--tape" => {
+                    let mode = args.next().ok_or("Missing mode after --tape")?;
+                    tape = parse_tape_mode(&mode)
+                        .ok_or_else(|| format!("Unknown tape mode '{mode}'"))?;
+                }
+                "--cell" => {
+                    let name = args.next().ok_or("Missing type after --cell")?;
+                    cell = parse_cell_type(&name)
+                        .ok_or_else(|| format!("Unknown cell type '{name}'"))?;
+                }
+                "--overflow" => {
+                    overflow = match args.next().as_deref() {
+                        Some("wrap") => OverflowMode::Wrapping,
+                        Some("saturate") => OverflowMode::Saturating,
+                        Some("trap") => OverflowMode::Trapping,
+                        Some(other) => return Err(format!("Unknown overflow mode '{other}'")),
+                        None => return Err("Missing mode after --overflow".to_string()),
+                    };
+                }
+                "--max-steps" | "--timeout" | "--max-cells" | "--max-depth" => {
+                    parse_limit_option(&arg, args.next(), &mut limits)?;
+                }
+                "--decode" => {
+                    output_mode = match args.next().as_deref() {
+                        Some("utf8") => OutputMode::Utf8,
+                        Some("latin1") => OutputMode::Latin1,
+                        Some("dec") => OutputMode::Decimal,
+                        Some("hex") => OutputMode::Hex,
+                        Some(other) => return Err(format!("Unknown output mode '{other}'")),
+                        None => return Err("Missing mode after --decode".to_string()),
+                    };
+                }
+                "--input" => {
+                    let text = args.next().ok_or("Missing text after --input")?;
+                    inputs.push(InputSource::Text(text));
+                }
+                "--input-file" => {
+                    let path = args.next().ok_or("Missing path after --input-file")?;
+                    inputs.push(InputSource::File(PathBuf::from(path)));
+                }
+                "--save-output" | "--dump-data" | "--dump-meta" | "--dump-format" => {
+                    parse_dump_option(&arg, args.next(), &mut dumps)?;
+                }
+                option if option.starts_with("--") => {
+                    return Err(format!("Unknown option '{option}'"));
+                }
+                _ if path.is_some() => return Err(format!("Unexpected argument '{arg}'")),
+                _ => path = Some(PathBuf::from(arg)),
+            }
+        }
+        // The clock keeps going while the visualizer is paused
+        if limits.time.is_some() && !headless {
+            return Err("--timeout only works with noseburn run".to_string());
+        }
+        Ok(Self {
+            headless,
+            path: path.ok_or("Provide a file path please")?,
+            entry,
+            eof,
+            tape,
+            cell,
+            overflow,
+            limits,
+            output_mode,
+            inputs,
+            dumps,
+        })
+    }
+
+    /// Load the program from its code `decoded`, and set the runner up as
+    /// asked
+    ///
+    /// # Errors
+    ///
+    /// Returns the report of what prevents the program from being loaded.
+    pub fn load<C: Cell>(&self, decoded: &str) -> Result<Runner<C>, String> {
+        let mut runner = Runner::new(decoded, &self.path).map_err(|errors| errors.render())?;
+        if let Some(entry) = &self.entry {
+            runner
+                .set_entry_point(Some(entry))
+                .map_err(|error| format!("error: {error}\n"))?;
+        }
+        runner.set_eof_policy(self.eof);
+        runner.set_limits(self.limits);
+        runner.set_overflow_mode(self.overflow);
+        runner.set_tape_mode(self.tape);
+        Ok(runner)
+    }
+
+    /// Gather the input of the program, followed by the standard input if it
+    /// does not come from a terminal and the program is not run headless, in
+    /// which case it reads the standard input as it goes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, naming the file if it comes from one, if an input
+    /// file or the standard input cannot be read.
+    pub fn read_input(&self) -> io::Result<Vec<u8>> {
+        let mut input: Vec<u8> = Vec::new();
+        for source in &self.inputs {
+            match source {
+                InputSource::Text(text) => input.extend_from_slice(text.as_bytes()),
+                InputSource::File(path) => {
+                    input.append(&mut std::fs::read(path).map_err(|error| {
+                        io::Error::new(error.kind(), format!("{}: {error}", path.display()))
+                    })?);
+                }
+            }
+        }
+        let mut stdin = io::stdin();
+        if !self.headless && !stdin.is_terminal() {
+            stdin.read_to_end(&mut input)?;
+        }
+        Ok(input)
+    }
+}
+
+/// Read a tape mode such as `infinite` or `circular:30`
+fn parse_tape_mode(mode: &str) -> Option<TapeMode> {
+    match mode.split_once(':') {
+        Some(("circular", cells)) => cells.parse().ok().map(TapeMode::Circular),
+        None if mode == "bounded" => Some(TapeMode::Bounded),
+        None if mode == "infinite" => Some(TapeMode::Infinite),
+        _ => None,
+    }
+}
+
+/// Read a cell type such as `u8` or `i32`
+fn parse_cell_type(name: &str) -> Option<CellType> {
+    match name {
+        "u8" => Some(CellType::U8),
+        "u16" => Some(CellType::U16),
+        "u32" => Some(CellType::U32),
+        "i8" => Some(CellType::I8),
+        "i16" => Some(CellType::I16),
+        "i32" => Some(CellType::I32),
+        _ => None,
+    }
+}
+
+/// Read the value given to one of the options telling what to save
+fn parse_dump_option(option: &str, value: Option<String>, dumps: &mut Dumps) -> Result<(), String> {
+    let file = match option {
+        "--save-output" => &mut dumps.output,
+        "--dump-data" => &mut dumps.data,
+        "--dump-meta" => &mut dumps.meta,
+        _ => {
+            dumps.format = match value.as_deref() {
+                Some("hex") => DumpFormat::Hex,
+                Some("raw") => DumpFormat::Raw,
+                Some("json") => DumpFormat::Json,
+                Some(other) => return Err(format!("Unknown dump format '{other}'")),
+                None => return Err(format!("Missing format after {option}")),
+            };
+            return Ok(());
+        }
+    };
+    *file = Some(PathBuf::from(
+        value.ok_or(format!("Missing path after {option}"))?,
+    ));
+    Ok(())
+}
+
+/// Read the value given to one of the options limiting the resources of the
+/// program
+fn parse_limit_option(
+    option: &str,
+    value: Option<String>,
+    limits: &mut Limits,
+) -> Result<(), String> {
+    let value = value.ok_or(format!("Missing limit after {option}"))?;
+    let invalid = || format!("Invalid limit '{value}' for {option}");
+    match option {
+        "--max-steps" => limits.steps = Some(value.parse().map_err(|_| invalid())?),
+        "--timeout" => {
+            let seconds: f64 = value.parse().map_err(|_| invalid())?;
+            limits.time = Some(Duration::try_from_secs_f64(seconds).map_err(|_| invalid())?);
+        }
+        "--max-cells" => limits.cells = Some(value.parse().map_err(|_| invalid())?),
+        _ if value == "unlimited" => limits.depth = None,
+        _ => limits.depth = Some(value.parse().map_err(|_| invalid())?),
+    }
+    Ok(())
+}
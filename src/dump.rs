@@ -0,0 +1,158 @@
+//! Saving the output and the ribbons to files
+
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use noseburn::moostar::{cell::Cell, Ribbon, Runner};
+
+/// How the ribbons are written to files
+#[derive(Clone, Copy, Default)]
+pub enum DumpFormat {
+    /// Rows of hexadecimal values, after the address of their first cell
+    #[default]
+    Hex,
+    /// Values one after the other, lowest byte first
+    Raw,
+    /// An object holding the address of the first cell and the values
+    Json,
+}
+
+impl DumpFormat {
+    /// Extension of the files written in this format
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Hex => "hex",
+            Self::Raw => "bin",
+            Self::Json => "json",
+        }
+    }
+
+    /// Write the values of the cells of `ribbon` from address `start`
+    fn encode<C: Cell>(self, ribbon: Ribbon, start: isize, cells: &[C]) -> Vec<u8> {
+        match self {
+            Self::Raw => cells.iter().flat_map(|value| value.to_bytes()).collect(),
+            Self::Hex => {
+                // Rows hold 16 bytes, whatever the width of the cells
+                let per_row = (16 / C::default().to_bytes().len()).max(1);
+                let mut dump = String::new();
+                for (address, values) in (start..).step_by(per_row).zip(cells.chunks(per_row)) {
+                    let hex: Vec<String> = values
+                        .iter()
+                        .map(|value| {
+                            value
+                                .to_bytes()
+                                .iter()
+                                .rev()
+                                .fold(String::new(), |mut hex, b| {
+                                    let _ = write!(hex, "{b:02x}");
+                                    hex
+                                })
+                        })
+                        .collect();
+                    let _ = writeln!(dump, "{address:>8}  {}", hex.join(" "));
+                }
+                dump.into_bytes()
+            }
+            Self::Json => {
+                let values: Vec<String> = cells.iter().map(ToString::to_string).collect();
+                format!(
+                    "{{\"ribbon\": \"{ribbon}\", \"start\": {start}, \"cells\": [{}]}}\n",
+                    values.join(", ")
+                )
+                .into_bytes()
+            }
+        }
+    }
+}
+
+/// Files to save the output and the ribbons to
+#[derive(Clone, Default)]
+pub struct Dumps {
+    /// File receiving the bytes of the output
+    pub output: Option<PathBuf>,
+    /// File receiving the touched cells of the data ribbon
+    pub data: Option<PathBuf>,
+    /// File receiving the touched cells of the meta ribbon
+    pub meta: Option<PathBuf>,
+    /// How the ribbons are written
+    pub format: DumpFormat,
+}
+
+impl Dumps {
+    /// Whether there is nothing to save
+    pub fn is_empty(&self) -> bool {
+        self.output.is_none() && self.data.is_none() && self.meta.is_none()
+    }
+
+    /// Save to the files given, and next to the program at `path` otherwise
+    pub fn or_next_to(&self, path: &Path) -> Self {
+        let extension = self.format.extension();
+        let or_default = |file: &Option<PathBuf>, default: &str| {
+            Some(file.clone().unwrap_or_else(|| path.with_extension(default)))
+        };
+        Self {
+            output: or_default(&self.output, "out"),
+            data: or_default(&self.data, &format!("data.{extension}")),
+            meta: or_default(&self.meta, &format!("meta.{extension}")),
+            format: self.format,
+        }
+    }
+
+    /// Write the output and the ribbons of `runner` to their files, and
+    /// return the paths written to
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the file that could not be written, in which
+    /// case the ones before it are already written.
+    pub fn save<C: Cell>(&self, runner: &Runner<C>) -> io::Result<Vec<&Path>> {
+        let write = |path: &Path, contents: &[u8]| {
+            std::fs::write(path, contents).map_err(|error| {
+                io::Error::new(error.kind(), format!("{}: {error}", path.display()))
+            })
+        };
+        let mut saved: Vec<&Path> = Vec::new();
+        if let Some(path) = &self.output {
+            write(path, runner.get_output())?;
+            saved.push(path);
+        }
+        for (ribbon, path) in [(Ribbon::Data, &self.data), (Ribbon::Meta, &self.meta)] {
+            if let Some(path) = path {
+                let (start, cells) = runner.get_touched_cells(ribbon).unwrap_or((0, Vec::new()));
+                write(path, &self.format.encode(ribbon, start, &cells))?;
+                saved.push(path);
+            }
+        }
+        Ok(saved)
+    }
+}
+
+/// Split `bytes` in rows of offset, hexadecimal values and printable
+/// characters, `per_row` bytes each
+pub fn hex_rows(bytes: &[u8], per_row: usize) -> Vec<String> {
+    let per_row = per_row.max(1);
+    bytes
+        .chunks(per_row)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+            let text: String = bytes
+                .iter()
+                .map(|b| {
+                    if b.is_ascii_graphic() || *b == b' ' {
+                        char::from(*b)
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:08x}  {:w$}  |{text}|",
+                row * per_row,
+                hex.join(" "),
+                w = 3 * per_row - 1
+            )
+        })
+        .collect()
+}
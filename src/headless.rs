@@ -0,0 +1,167 @@
+//! Running programs without the visualizer
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+
+use crate::cli::{CellType, Options};
+use crate::dump::Dumps;
+use noseburn::moostar::{cell::Cell, EofPolicy, StepOutcome};
+
+/// Exit status once the program halts
+const HALTED: i32 = 0;
+/// Exit status when the program fails
+const FAILED: i32 = 1;
+/// Exit status when the program cannot be loaded
+const UNLOADABLE: i32 = 3;
+/// Exit status when the program goes past one of its limits
+const LIMITED: i32 = 4;
+
+/// Run the program at full speed on the standard input and output, and
+/// return the exit status
+pub fn run(options: &Options) -> i32 {
+    let decoded = std::fs::read(&options.path)
+        .map_err(|error| error.to_string())
+        .and_then(|bytes| String::from_utf8(bytes).map_err(|error| error.to_string()));
+    let decoded = match decoded {
+        Ok(decoded) => decoded,
+        Err(error) => {
+            eprintln!("error: {}: {error}", options.path.display());
+            return UNLOADABLE;
+        }
+    };
+    let status = match options.cell {
+        CellType::U8 => run_with::<u8>(&decoded, options),
+        CellType::U16 => run_with::<u16>(&decoded, options),
+        CellType::U32 => run_with::<u32>(&decoded, options),
+        CellType::I8 => run_with::<i8>(&decoded, options),
+        CellType::I16 => run_with::<i16>(&decoded, options),
+        CellType::I32 => run_with::<i32>(&decoded, options),
+    };
+    status.unwrap_or_else(|error| {
+        // Whoever reads the output stopped, nothing more to tell them
+        if error.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("error: {error}");
+        }
+        FAILED
+    })
+}
+
+/// Run the program with cells of type `C`
+fn run_with<C: Cell>(decoded: &str, options: &Options) -> io::Result<i32> {
+    let mut runner = match options.load::<C>(decoded) {
+        Ok(runner) => runner,
+        Err(report) => {
+            eprint!("{report}");
+            return Ok(UNLOADABLE);
+        }
+    };
+    runner.push_input(&options.read_input()?);
+    // The end of the input is the end of the standard input, not of what was
+    // read from it so far
+    runner.set_eof_policy(EofPolicy::Wait);
+    let stdin = read_stdin();
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    // The output is let go of once written, so the file saving it is written
+    // along the way rather than at the end
+    let mut saved = match &options.dumps.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some((path, io::BufWriter::new(file))),
+            Err(error) => {
+                eprintln!("error: could not save {}: {error}", path.display());
+                return Ok(FAILED);
+            }
+        },
+        None => None,
+    };
+    let status = loop {
+        let outcome = runner.step();
+        let output = runner.take_output();
+        stdout.write_all(&output)?;
+        if let Some((path, file)) = &mut saved {
+            file.write_all(&output)
+                .map_err(|error| not_saved(path, &error))?;
+        }
+        match outcome {
+            Ok(StepOutcome::Ran) => {}
+            Ok(StepOutcome::Waiting) => {
+                // Show what the program asks for before waiting for it
+                stdout.flush()?;
+                let received = match runner.get_time_left() {
+                    Some(left) => match stdin.recv_timeout(left) {
+                        Ok(received) => received,
+                        // Stepping again once the time is up reports it
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => Ok(Vec::new()),
+                    },
+                    None => stdin.recv().unwrap_or_else(|_| Ok(Vec::new())),
+                };
+                let chunk = received?;
+                if chunk.is_empty() && options.eof != EofPolicy::Wait {
+                    runner.set_eof_policy(options.eof);
+                } else if chunk.is_empty() {
+                    eprintln!(
+                        "error: the program waits for input past the end of the standard input\n\
+                         help: --eof chooses what `,` reads there instead"
+                    );
+                    break FAILED;
+                }
+                runner.push_input(&chunk);
+            }
+            Ok(StepOutcome::Halted) => break HALTED,
+            Err(error) => {
+                eprint!("{}", error.render(runner.get_sources()));
+                if error.kind().is_limit() {
+                    eprintln!("note: stopped after {} steps", runner.get_step_count());
+                    break LIMITED;
+                }
+                break FAILED;
+            }
+        }
+    };
+    stdout.flush()?;
+    if let Some((path, file)) = &mut saved {
+        file.flush().map_err(|error| not_saved(path, &error))?;
+    }
+    let ribbons = Dumps {
+        output: None,
+        ..options.dumps.clone()
+    };
+    if let Err(error) = ribbons.save(&runner) {
+        eprintln!("error: could not save {error}");
+        return Ok(FAILED);
+    }
+    Ok(status)
+}
+
+/// Read the standard input from another thread, so that waiting for it can be
+/// given up on, in chunks ending with an empty one
+fn read_stdin() -> Receiver<io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut chunk = [0; 4096];
+        loop {
+            let read = stdin.read(&mut chunk);
+            let last = !matches!(read, Ok(read) if read > 0);
+            if sender
+                .send(read.map(|read| chunk[..read].to_vec()))
+                .is_err()
+                || last
+            {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Error telling `path` could not be saved
+fn not_saved(path: &Path, error: &io::Error) -> io::Error {
+    io::Error::new(
+        error.kind(),
+        format!("could not save {}: {error}", path.display()),
+    )
+}
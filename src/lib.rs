@@ -0,0 +1,13 @@
+//! Moostar parsing and running, shared by the noseburn visualizer
+// Make clippy quite nasty
+#![deny(clippy::complexity)]
+#![deny(clippy::correctness)]
+#![deny(clippy::style)]
+#![deny(clippy::pedantic)]
+#![deny(clippy::perf)]
+// Add some new clippy lints
+#![deny(clippy::use_self)]
+// Add some default lints
+#![deny(unused_variables)]
+
+pub mod moostar;
@@ -11,13 +11,16 @@
 #![deny(unused_variables)]
 
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
 use std::{
     error::Error,
+    fmt::Write as _,
     io,
     time::{Duration, Instant},
 };
@@ -27,15 +30,21 @@ use tui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, BorderType, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    widgets::{Block, BorderType, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
 
-mod moostar;
+use noseburn::moostar::{self, cell::Cell};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+mod cli;
+mod dump;
+mod headless;
+
+#[derive(PartialEq, Eq)]
 enum InputEditionMode {
     Normal,
-    //Editing
+    Editing,
 }
 
 #[derive(Copy, Clone)]
@@ -54,124 +63,452 @@ enum Frequency {
     Thousand,
 }
 
-struct App {
+/// Layout of the Output panel
+struct OutputView {
+    /// Wrap long lines instead of cutting them
+    wrap: bool,
+    /// Show a hex dump next to the text
+    hex: bool,
+    /// Take the place of every other panel
+    fullscreen: bool,
+    /// Rows scrolled back from the end
+    scroll: usize,
+    /// Rows the panel showed and could scroll back when last drawn
+    page: std::cell::Cell<(usize, usize)>,
+}
+
+impl Default for OutputView {
+    fn default() -> Self {
+        Self {
+            wrap: true,
+            hex: false,
+            fullscreen: false,
+            scroll: 0,
+            page: std::cell::Cell::new((1, 0)),
+        }
+    }
+}
+
+struct App<C: Cell> {
     /// Runner
-    runner: moostar::Runner,
-    /// Keep a separate, original version of the code here
-    code: String,
+    runner: moostar::Runner<C>,
+    /// Keep a separate, original version of the code of every file here
+    code: Vec<String>,
     /// Input Edition Mode
     edition_mode: InputEditionMode,
+    /// Where typed input goes, in bytes
+    input_cursor: usize,
     /// The Frequency we are set at
     frequency: Frequency,
     /// Running
     running: bool,
-    /// Debug
-    funny_number: u16,
+    /// What stopped the program, if it failed
+    error: Option<moostar::RuntimeError>,
+    /// How the output is shown
+    output_mode: moostar::OutputMode,
+    /// How the Output panel is laid out
+    output_view: OutputView,
+    /// Files to save to when the program stops
+    dumps: dump::Dumps,
+    /// Files to save to when asked to
+    dumps_on_demand: dump::Dumps,
+    /// Whether the program stopped since it was last reset
+    stopped: bool,
+    /// What happened after the last key press
+    message: Option<String>,
 }
 
-impl App {
-    fn new(path: &str) -> Result<Self, Box<dyn Error>> {
-        let vecbytes: Vec<u8> = std::fs::read(path)?;
-        let decoded: String = String::from_utf8(vecbytes)?;
+impl<C: Cell> App<C> {
+    /// Load the program, or explain why it cannot be run
+    fn new(decoded: &str, options: &cli::Options, input: &[u8]) -> Result<Self, String> {
+        let mut runner = options.load(decoded)?;
+        runner.push_input(input);
+        let code = runner
+            .get_sources()
+            .iter()
+            .map(|source| source.text.clone() + " ") // That space serves for "halt"
+            .collect();
         Ok(Self {
-            runner: moostar::Runner::new(&decoded)?,
-            code: decoded + " ", // That space serves for "halt"
+            runner,
+            code,
             edition_mode: InputEditionMode::Normal,
+            input_cursor: 0,
             frequency: Frequency::One,
             running: false,
-            funny_number: 0,
+            error: None,
+            output_mode: options.output_mode,
+            output_view: OutputView::default(),
+            dumps: options.dumps.clone(),
+            dumps_on_demand: options.dumps.or_next_to(&options.path),
+            stopped: false,
+            message: None,
         })
     }
 
     fn reset(&mut self) {
         self.runner.reset();
         self.running = false;
+        self.error = None;
+        self.stopped = false;
     }
 
     fn step(&mut self) {
-        self.runner.step();
+        // A failed program stays where it failed until reset
+        if self.error.is_some() {
+            return;
+        }
+        let was_waiting = self.runner.is_waiting();
+        match self.runner.step() {
+            // Pause and let the user provide what the program is waiting for,
+            // once, so that leaving the Input panel sticks
+            Ok(moostar::StepOutcome::Waiting) => {
+                if !was_waiting {
+                    self.running = false;
+                    if self.edition_mode == InputEditionMode::Normal {
+                        self.start_editing();
+                    }
+                }
+            }
+            Ok(moostar::StepOutcome::Halted) => self.stop(),
+            Ok(moostar::StepOutcome::Ran) => {}
+            Err(error) => {
+                self.error = Some(error);
+                self.running = false;
+                self.stop();
+            }
+        }
     }
 
-    fn get_input(&self) -> &str {
-        self.runner.get_input()
+    /// Save what the program left once it halted or failed
+    fn stop(&mut self) {
+        if !self.stopped {
+            self.stopped = true;
+            if !self.dumps.is_empty() {
+                self.save(false);
+            }
+        }
     }
 
-    fn get_output(&self) -> &str {
-        self.runner.get_output()
+    /// Save the output and the ribbons, to the files given on the command
+    /// line or to the ones next to the program if `on_demand`
+    fn save(&mut self, on_demand: bool) {
+        let dumps = if on_demand {
+            &self.dumps_on_demand
+        } else {
+            &self.dumps
+        };
+        self.message = Some(match dumps.save(&self.runner) {
+            Ok(paths) => {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                format!("Saved {}", paths.join(", "))
+            }
+            Err(error) => format!("Could not save {error}"),
+        });
+    }
+
+    fn start_editing(&mut self) {
+        self.edition_mode = InputEditionMode::Editing;
+        self.input_cursor = self.runner.get_input().len();
+    }
+
+    /// Handle a key pressed while editing the input
+    fn edit_input(&mut self, key: KeyEvent) {
+        let glyphs = self.get_input_glyphs();
+        let input_len = self.runner.get_input().len();
+        // Consumed input cannot be edited anymore
+        let cursor = self
+            .input_cursor
+            .clamp(self.runner.get_input_position(), input_len);
+        let previous = glyphs
+            .iter()
+            .rev()
+            .map(|(start, _)| *start)
+            .find(|start| *start < cursor)
+            .map_or(cursor, |start| start.max(self.runner.get_input_position()));
+        let next = glyphs
+            .iter()
+            .map(|(start, _)| *start)
+            .find(|start| *start > cursor)
+            .unwrap_or(input_len);
+        // Shortcuts are not text
+        if key
+            .modifiers
+            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+        {
+            return;
+        }
+        self.input_cursor = match key.code {
+            KeyCode::Esc => {
+                self.edition_mode = InputEditionMode::Normal;
+                cursor
+            }
+            KeyCode::Left => previous,
+            KeyCode::Right => next,
+            KeyCode::Home => self.runner.get_input_position(),
+            KeyCode::End => input_len,
+            KeyCode::Backspace => {
+                self.runner.remove_input(previous..cursor);
+                previous
+            }
+            KeyCode::Delete => {
+                self.runner.remove_input(cursor..next);
+                cursor
+            }
+            KeyCode::Enter => self.insert_char(cursor, '\n'),
+            KeyCode::Tab => self.insert_char(cursor, '\t'),
+            KeyCode::Char(c) => self.insert_char(cursor, c),
+            _ => cursor,
+        };
+    }
+
+    /// Insert a character in the input, returning the position after it
+    fn insert_char(&mut self, at: usize, c: char) -> usize {
+        let mut buffer = [0; 4];
+        let bytes = c.encode_utf8(&mut buffer).as_bytes();
+        self.runner.insert_input(at, bytes);
+        at + bytes.len()
+    }
+
+    /// Split the input in characters, along with the position of their first
+    /// byte, and how to show them
+    fn get_input_glyphs(&self) -> Vec<(usize, char)> {
+        let input = self.runner.get_input();
+        let mut glyphs: Vec<(usize, char)> = Vec::new();
+        let mut start: usize = 0;
+        for chunk in input.utf8_chunks() {
+            for (offset, c) in chunk.valid().char_indices() {
+                let shown = match c {
+                    '\n' => '⏎',
+                    '\t' => '⇥',
+                    c if c.is_control() => '·',
+                    c => c,
+                };
+                glyphs.push((start + offset, shown));
+            }
+            start += chunk.valid().len();
+            if !chunk.invalid().is_empty() {
+                glyphs.push((start, char::REPLACEMENT_CHARACTER));
+                start += chunk.invalid().len();
+            }
+        }
+        glyphs
+    }
+
+    /// Build the contents of the input panel, consumed input dimmed, along
+    /// with the column of the cursor
+    fn get_input_text(&self) -> (Spans<'_>, usize) {
+        let consumed_style = Style::default().fg(Color::DarkGray);
+        let cursor_style = Style::default().add_modifier(Modifier::REVERSED);
+        let editing = self.edition_mode == InputEditionMode::Editing;
+        let position = self.runner.get_input_position();
+        let cursor = self
+            .input_cursor
+            .clamp(position, self.runner.get_input().len());
+        let mut column: usize = 0;
+        let mut cursor_column: usize = 0;
+        let mut spans: Vec<Span> = Vec::new();
+        for (start, glyph) in self.get_input_glyphs() {
+            let style = if editing && start == cursor {
+                cursor_column = column;
+                cursor_style
+            } else if start < position {
+                consumed_style
+            } else {
+                Style::default()
+            };
+            column += glyph.width().unwrap_or(0);
+            spans.push(Span::styled(glyph.to_string(), style));
+        }
+        if editing && cursor == self.runner.get_input().len() {
+            cursor_column = column;
+            spans.push(Span::styled(" ", cursor_style));
+        }
+        (Spans::from(spans), cursor_column)
+    }
+
+    /// Split the decoded output in the rows to show in a pane `width` wide
+    fn get_output_rows(&self, width: usize) -> Vec<String> {
+        let width = width.max(1);
+        let output = self.output_mode.decode(self.runner.get_output());
+        let mut rows: Vec<String> = Vec::new();
+        for line in output.split('\n') {
+            let line = moostar::display_line(line);
+            if !self.output_view.wrap {
+                rows.push(line);
+                continue;
+            }
+            let mut row = String::new();
+            let mut row_width: usize = 0;
+            for c in line.chars() {
+                let c_width = c.width().unwrap_or(0);
+                if row_width + c_width > width {
+                    rows.push(std::mem::take(&mut row));
+                    row_width = 0;
+                }
+                row.push(c);
+                row_width += c_width;
+            }
+            rows.push(row);
+        }
+        rows
     }
 
-    fn get_output_length(&self) -> usize {
-        self.runner.get_output_length()
+    /// Split the output in rows of offset, hexadecimal values and printable
+    /// characters, with as many bytes per row as fit in `width`
+    fn get_hex_rows(&self, width: usize) -> Vec<String> {
+        // Every byte takes three columns of hex and one of text
+        let per_row = [16, 8, 4]
+            .into_iter()
+            .find(|n| 4 * n + 13 <= width)
+            .unwrap_or(2);
+        dump::hex_rows(self.runner.get_output(), per_row)
     }
 
-    fn get_code(&self) -> &str {
-        &self.code
+    /// Scroll the output back by a page, or forward if `back` is false
+    fn scroll_output(&mut self, back: bool) {
+        let (page, max_scroll) = self.output_view.page.get();
+        self.output_view.scroll = if back {
+            (self.output_view.scroll + page).min(max_scroll)
+        } else {
+            self.output_view.scroll.saturating_sub(page)
+        };
     }
 
-    fn get_jumps(&self, max_of: Option<usize>) -> Text {
-        let style = Style::default()
+    fn get_jumps(&self, max_of: Option<usize>) -> Text<'_> {
+        let loop_style = Style::default()
             .fg(Color::Green)
             .add_modifier(Modifier::BOLD);
+        let call_style = Style::default()
+            .fg(Color::Cyan)
+            .add_modifier(Modifier::BOLD);
+        let sources = self.runner.get_sources();
+        // Where a frame comes from, as line and column
+        let location = |span: moostar::Span| {
+            let (line, _, column) = sources[span.file].locate(span.start);
+            format!(" from {line}:{}", column + 1)
+        };
         Text::from(
             self.runner
-                .jump_list(max_of)
-                .iter()
-                .map(|pos| {
-                    Spans::from(vec![
-                        Span::raw("Back to #"),
-                        Span::styled(pos.to_string(), style),
-                    ])
+                .get_frames(max_of)
+                .into_iter()
+                .map(|frame| match frame {
+                    moostar::Frame::Loop { start } => Spans::from(vec![
+                        Span::styled("[loop]", loop_style),
+                        Span::raw(location(start)),
+                    ]),
+                    moostar::Frame::Call {
+                        function,
+                        call,
+                        collapsed,
+                    } => {
+                        let mut spans = vec![
+                            Span::styled(format!("~{function};"), call_style),
+                            Span::raw(location(call)),
+                        ];
+                        // Tail calls reuse the frame of their caller
+                        if collapsed > 0 {
+                            spans.push(Span::styled(
+                                format!(" +{collapsed} tail"),
+                                Style::default().fg(Color::Magenta),
+                            ));
+                        }
+                        Spans::from(spans)
+                    }
                 })
                 .collect::<Vec<Spans>>(),
         )
     }
 
-    fn get_coloured_code(&self, wrap_length: u16) -> (Text, usize) {
-        // Find out where to split
-        let wrap_length: usize = wrap_length.into();
-        let mut colour_span: (usize, usize) = self.runner.get_instruction_span();
-        let highlight_style = Style::default().fg(Color::Red).add_modifier(Modifier::BOLD);
+    /// Title of the jumps panel, with the deepest the calls went
+    fn get_jumps_title(&self) -> String {
+        let (_, call_high) = self.runner.get_stack_high_water();
+        format!("-::[Jumps, peak {call_high}]::-")
+    }
+
+    fn get_coloured_code(&self, wrap_length: u16) -> (Text<'_>, usize) {
+        let wrap_length: usize = usize::from(wrap_length).max(1);
+        let highlight_style = if self.error.is_some() {
+            Style::default()
+                .fg(Color::White)
+                .bg(Color::Red)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+        };
+        let comment_style = Style::default()
+            .fg(Color::DarkGray)
+            .add_modifier(Modifier::ITALIC);
+        // Show the file holding the next instruction
+        let file = self.runner.get_instruction_span().file;
+        // Later ranges take precedence over earlier ones
+        let mut styled: Vec<((usize, usize), Style)> = self
+            .runner
+            .get_comment_spans()
+            .into_iter()
+            .filter(|span| span.file == file)
+            .map(|span| ((span.start, span.len), comment_style))
+            .collect();
+        let highlight: Option<(usize, usize)> = (!self.runner.is_halted()).then(|| {
+            let span = self.runner.get_instruction_span();
+            (span.start, span.len)
+        });
+        if let Some(span) = highlight {
+            styled.push((span, highlight_style));
+        }
         // Split the code into texts
         let mut spans: Vec<Spans> = Vec::new();
-        let mut split_reached: bool = false;
+        let mut line_start: usize = 0;
+        let mut wrapped_lines: usize = 0;
         let mut center_line: usize = 0;
-        for (current_line, line) in self.code.lines().enumerate() {
-            let len = line.len();
-            // If the remainder of the line is more than the first split, ret
-            if colour_span.0 > len {
-                // Change the wrap offset
-                center_line += if len > wrap_length {
-                    len.div_euclid(wrap_length) + usize::from(len % wrap_length == 0)
-                } else {
-                    0
-                };
-                colour_span.0 -= line.len() + 1;
-                spans.push(Spans::from(vec![Span::raw(line)]));
-            } else if split_reached || self.runner.is_halted() {
-                spans.push(Spans::from(vec![Span::raw(line)]));
-            } else {
-                //Split into parts
-                // This is the line
-                center_line += current_line
-                    + if colour_span.0 > wrap_length {
-                        colour_span.0.div_euclid(wrap_length)
-                    } else {
-                        0
-                    };
-                let (one, bet) = line.split_at(colour_span.0);
-                let (two, three) = bet.split_at(colour_span.1);
-                spans.push(Spans::from(vec![
-                    Span::raw(one),
-                    Span::styled(two, highlight_style),
-                    Span::raw(three),
-                ]));
-                split_reached = true;
+        for line in self.code[file].split('\n') {
+            let style_at = |pos: usize| {
+                styled
+                    .iter()
+                    .rev()
+                    .find(|((start, len), _)| (*start..start + len).contains(&pos))
+                    .map_or_else(Style::default, |(_, style)| *style)
+            };
+            // Group characters sharing the same style together
+            let mut line_spans: Vec<Span> = Vec::new();
+            let mut current: (String, Style) = (String::new(), style_at(line_start));
+            let mut column: usize = 0;
+            for (offset, c) in line.char_indices() {
+                let pos = line_start + offset;
+                // Remember where the highlighted instruction is
+                if highlight.map(|(start, _)| start) == Some(pos) {
+                    center_line = wrapped_lines + column / wrap_length;
+                }
+                let style = style_at(pos);
+                if style != current.1 {
+                    let (text, previous) = std::mem::replace(&mut current, (String::new(), style));
+                    line_spans.push(Span::styled(text, previous));
+                }
+                let width = moostar::char_width(c, column);
+                if c == '\t' {
+                    current.0.extend(std::iter::repeat_n(' ', width));
+                } else if !c.is_control() {
+                    current.0.push(c);
+                }
+                column += width;
             }
+            line_spans.push(Span::styled(current.0, current.1));
+            spans.push(Spans::from(line_spans));
+            wrapped_lines += column.div_ceil(wrap_length).max(1);
+            line_start += line.len() + 1;
         }
         (Text::from(spans), center_line)
     }
 
-    fn get_ribbon(&self, count: usize) -> (Vec<u8>, usize) {
+    fn get_code_title(&self) -> String {
+        let file = self.runner.get_instruction_span().file;
+        let path = &self.runner.get_sources()[file].path;
+        match path.file_name() {
+            Some(name) if file > 0 => format!("-::[Code: {}]::-", name.to_string_lossy()),
+            _ => "-::[Code]::-".to_string(),
+        }
+    }
+
+    fn get_ribbon(&self, count: usize) -> (Vec<(isize, C)>, isize) {
         (
             self.runner.get_ribbon_around(count),
             self.runner.get_data_pointer(),
@@ -248,8 +585,9 @@ impl App {
 
     fn get_wrapped_code_line_count(&self, size: u16) -> u16 {
         let mut count: u16 = 0;
-        for line in self.code.lines() {
-            let len = line.len();
+        let file = self.runner.get_instruction_span().file;
+        for line in self.code[file].split('\n') {
+            let len = moostar::display_line(line).width().max(1);
             let rem = len.rem_euclid(size.into());
             let add: u16 = (len.div_euclid(size.into()) + usize::from(rem > 0))
                 .try_into()
@@ -282,15 +620,55 @@ fn disable_terminal<B: Backend + std::io::Write>(
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // Fetch argument
-    let mut args = std::env::args();
-    if args.len() < 2 {
-        eprintln!("Provide a file path please");
-        return Ok(());
+    // Fetch arguments
+    let options = match cli::Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if options.headless {
+        std::process::exit(headless::run(&options));
     }
+    let vecbytes: Vec<u8> = std::fs::read(&options.path)?;
+    let decoded: String = String::from_utf8(vecbytes)?;
+    let input: Vec<u8> = match options.read_input() {
+        Ok(input) => input,
+        Err(error) => {
+            eprintln!("error: {error}");
+            std::process::exit(1);
+        }
+    };
+    match options.cell {
+        cli::CellType::U8 => visualize::<u8>(&decoded, &options, &input),
+        cli::CellType::U16 => visualize::<u16>(&decoded, &options, &input),
+        cli::CellType::U32 => visualize::<u32>(&decoded, &options, &input),
+        cli::CellType::I8 => visualize::<i8>(&decoded, &options, &input),
+        cli::CellType::I16 => visualize::<i16>(&decoded, &options, &input),
+        cli::CellType::I32 => visualize::<i32>(&decoded, &options, &input),
+    }
+}
+
+/// Run the visualizer on the program, with cells of type `C`
+fn visualize<C: Cell>(
+    decoded: &str,
+    options: &cli::Options,
+    input: &[u8],
+) -> Result<(), Box<dyn Error>> {
     // Set it up
     let mut terminal = init_terminal()?;
-    let app = App::new(&args.nth(1).unwrap())?;
+    let app = match App::<C>::new(decoded, options, input) {
+        Ok(app) => app,
+        Err(report) => {
+            // Show what went wrong before giving the terminal back
+            let res = run_diagnostic(&mut terminal, &report);
+            disable_terminal(terminal)?;
+            eprint!("{report}");
+            res?;
+            std::process::exit(1);
+        }
+    };
     let res = run_app(&mut terminal, app);
 
     // restore it
@@ -303,7 +681,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
+fn run_app<C: Cell, B: Backend>(terminal: &mut Terminal<B>, mut app: App<C>) -> io::Result<()> {
     let mut last_tick = Instant::now();
     loop {
         terminal.draw(|f| ui(f, &app))?;
@@ -316,8 +694,29 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
         // Use all of that remaining time to try and fetch a key event
         if crossterm::event::poll(timeout)? {
             if let Event::Key(key) = event::read()? {
+                if is_quit(key) {
+                    return Ok(());
+                }
+                if app.edition_mode == InputEditionMode::Editing {
+                    app.edit_input(key);
+                    // Pasted text comes as a burst of keys, take them all
+                    // before drawing again
+                    while app.edition_mode == InputEditionMode::Editing
+                        && crossterm::event::poll(Duration::ZERO)?
+                    {
+                        if let Event::Key(key) = event::read()? {
+                            if is_quit(key) {
+                                return Ok(());
+                            }
+                            app.edit_input(key);
+                        }
+                    }
+                    continue;
+                }
+                app.message = None;
                 match key.code {
                     KeyCode::Char('q') => return Ok(()),
+                    KeyCode::Char('d') => app.save(true),
                     KeyCode::Up => app.decrease_frequency(),
                     KeyCode::Down => app.increase_frequency(),
                     KeyCode::Char(' ') => app.running = !app.running,
@@ -328,6 +727,14 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                     KeyCode::Char('r') => {
                         app.reset();
                     }
+                    KeyCode::Char('i') => app.start_editing(),
+                    KeyCode::Char('e') => app.output_mode = app.output_mode.next(),
+                    KeyCode::Char('w') => app.output_view.wrap = !app.output_view.wrap,
+                    KeyCode::Char('x') => app.output_view.hex = !app.output_view.hex,
+                    KeyCode::Char('o') => app.output_view.fullscreen = !app.output_view.fullscreen,
+                    KeyCode::Esc => app.output_view.fullscreen = false,
+                    KeyCode::PageUp => app.scroll_output(true),
+                    KeyCode::PageDown => app.scroll_output(false),
                     _ => {}
                 }
             }
@@ -352,60 +759,255 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
     }
 }
 
-fn ui_io<B: Backend>(f: &mut Frame<B>, app: &App, chunks: &[Rect]) {
-    let io_layout = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-        .split(chunks[1]);
-    let input_block = Paragraph::new(app.get_input())
+/// Whether `key` quits, which works even while editing the input
+fn is_quit(key: KeyEvent) -> bool {
+    key.modifiers
+        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+        && matches!(key.code, KeyCode::Char('c' | 'q'))
+}
+
+fn run_diagnostic<B: Backend>(terminal: &mut Terminal<B>, report: &str) -> io::Result<()> {
+    loop {
+        terminal.draw(|f| ui_diagnostic(f, report))?;
+        if let Event::Key(key) = event::read()? {
+            if let KeyCode::Char('q') | KeyCode::Esc | KeyCode::Enter = key.code {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn ui_diagnostic<B: Backend>(f: &mut Frame<B>, report: &str) {
+    let size = f.size();
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([Constraint::Min(5), Constraint::Length(2)].as_ref())
+        .split(size);
+
+    let report_block = Paragraph::new(report)
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .border_style(Style::reset())
                 .title(Span::styled(
-                    "Input",
+                    "Could not load program",
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                ))
+                .title_alignment(Alignment::Center)
+                .border_type(BorderType::Thick),
+        )
+        .wrap(Wrap { trim: false });
+    f.render_widget(report_block, chunks[0]);
+
+    let help_block = Paragraph::new("Q: Quit")
+        .block(
+            Block::default()
+                .borders(Borders::TOP)
+                .title(Span::styled(
+                    "Keys",
                     Style::default()
                         .fg(Color::Red)
                         .add_modifier(Modifier::ITALIC),
                 ))
-                .title_alignment(Alignment::Right)
+                .title_alignment(Alignment::Center)
                 .border_type(BorderType::Plain),
         )
-        .style(
-            Style::default()
-                .fg(Color::Yellow)
-                .add_modifier(Modifier::BOLD),
+        .style(Style::default().fg(Color::White).bg(Color::Black))
+        .alignment(Alignment::Center);
+    f.render_widget(help_block, chunks[1]);
+}
+
+/// Show the cells of the data ribbon around the pointer, with their addresses
+fn ui_ribbon<C: Cell, B: Backend>(f: &mut Frame<B>, app: &App<C>, area: Rect) {
+    // Every cell is its value with a space before it and a bar after it
+    let width = C::WIDTH;
+    let cell_count = usize::from(area.width.saturating_sub(2)) / (width + 3);
+    let (rdata, position) = app.get_ribbon(cell_count);
+    let mut address_spans: Vec<Span<'_>> = rdata
+        .iter()
+        .map(|(address, _)| Span::raw(format!("{address:^w$} ", w = width + 2)))
+        .collect::<Vec<Span>>();
+    address_spans.insert(0, Span::raw(" "));
+    let mut ribbon_spans: Vec<Span<'_>> = rdata
+        .iter()
+        .map(|(_, x)| Span::raw(format!(" {x:0width$} |")))
+        .collect::<Vec<Span>>();
+    ribbon_spans.insert(0, Span::raw("|"));
+    // Point at the middle of the value
+    let caret = format!(
+        "{}^{}",
+        " ".repeat(1 + width / 2),
+        " ".repeat(width + 1 - width / 2)
+    );
+    let blank = " ".repeat(width + 3);
+    let mut caret_spans: Vec<Span<'_>> = rdata
+        .iter()
+        .map(|(address, _)| {
+            Span::raw(if *address == position {
+                caret.clone()
+            } else {
+                blank.clone()
+            })
+        })
+        .collect::<Vec<Span>>();
+    caret_spans.insert(0, Span::raw(" "));
+    let ribbon_block = Paragraph::new(Text::from(vec![
+        Spans::from(address_spans),
+        Spans::from(ribbon_spans),
+        Spans::from(caret_spans),
+    ]))
+    .block(
+        Block::default()
+            .title(format!("Ribbons ({})", app.runner.get_tape_mode()))
+            .borders(Borders::ALL),
+    )
+    .alignment(Alignment::Center);
+    f.render_widget(ribbon_block, area);
+}
+
+/// Show what made the program fail at the bottom of `area`
+fn ui_error<C: Cell, B: Backend>(f: &mut Frame<B>, app: &App<C>, area: Rect) {
+    let Some(error) = &app.error else {
+        return;
+    };
+    let report = error.render(app.runner.get_sources());
+    // Leave the ribbon itself visible
+    let height = u16::try_from(report.lines().count() + 2)
+        .unwrap_or(u16::MAX)
+        .min(area.height.saturating_sub(4));
+    let banner_area = Rect::new(
+        area.x + 1,
+        (area.y + area.height).saturating_sub(height + 1),
+        area.width.saturating_sub(2),
+        height,
+    );
+    let banner = Paragraph::new(report)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(Span::styled(
+                    "Program failed, R: Reset",
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                ))
+                .title_alignment(Alignment::Center)
+                .border_type(BorderType::Thick),
         )
-        .alignment(Alignment::Left);
-    f.render_widget(input_block, io_layout[0]);
+        .style(Style::default().fg(Color::Red))
+        .wrap(Wrap { trim: false });
+    f.render_widget(Clear, banner_area);
+    f.render_widget(banner, banner_area);
+}
 
-    let out = app.get_output();
-    let outlen: u16 = u16::try_from(app.get_output_length()).expect("Output overflowed u16");
-    let outscroll: u16 = outlen.saturating_sub(io_layout[1].width);
-    let output_block = Paragraph::new(out)
+fn ui_io<C: Cell, B: Backend>(f: &mut Frame<B>, app: &App<C>, chunks: &[Rect]) {
+    let io_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(chunks[1]);
+    let input_title = match (&app.edition_mode, app.runner.is_waiting()) {
+        (InputEditionMode::Editing, true) => "Input (waiting, typing)",
+        (InputEditionMode::Editing, false) => "Input (typing)",
+        (InputEditionMode::Normal, true) => "Input (waiting)",
+        (InputEditionMode::Normal, false) => "Input",
+    };
+    let (input, cursor_column) = app.get_input_text();
+    // Keep the cursor in sight
+    let input_scroll =
+        cursor_column.saturating_sub(usize::from(io_layout[0].width.saturating_sub(3)));
+    let input_block = Paragraph::new(input)
         .block(
             Block::default()
                 .borders(Borders::ALL)
                 .border_style(Style::reset())
                 .title(Span::styled(
-                    "Output",
+                    input_title,
                     Style::default()
                         .fg(Color::Red)
                         .add_modifier(Modifier::ITALIC),
                 ))
-                .title_alignment(Alignment::Right),
+                .title_alignment(Alignment::Right)
+                .border_type(BorderType::Plain),
         )
         .style(
             Style::default()
                 .fg(Color::Yellow)
                 .add_modifier(Modifier::BOLD),
         )
-        .scroll((0, outscroll))
+        .scroll((0, u16::try_from(input_scroll).unwrap_or(u16::MAX)))
         .alignment(Alignment::Left);
-    f.render_widget(output_block, io_layout[1]);
+    f.render_widget(input_block, io_layout[0]);
+
+    ui_output(f, app, io_layout[1]);
+}
+
+/// Show the output in `area`, next to its hex dump if asked to
+fn ui_output<C: Cell, B: Backend>(f: &mut Frame<B>, app: &App<C>, area: Rect) {
+    let mut title = format!("Output ({}", app.output_mode);
+    if !app.output_view.wrap {
+        title.push_str(", no wrap");
+    }
+    if app.output_view.scroll > 0 {
+        let _ = write!(title, ", {} rows back", app.output_view.scroll);
+    }
+    title.push(')');
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::reset())
+        .title(Span::styled(
+            title,
+            Style::default()
+                .fg(Color::Red)
+                .add_modifier(Modifier::ITALIC),
+        ))
+        .title_alignment(Alignment::Right);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let panes = if app.output_view.hex {
+        Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .split(inner)
+    } else {
+        vec![inner]
+    };
+    let height = usize::from(inner.height);
+    let mut max_scroll: usize = 0;
+    let mut contents = vec![(panes[0], app.get_output_rows(usize::from(panes[0].width)))];
+    if app.output_view.hex {
+        contents.push((panes[1], app.get_hex_rows(usize::from(panes[1].width))));
+    }
+    for (pane, rows) in contents {
+        // Rows are counted back from the end, so that new output shows up
+        let pane_scroll = rows.len().saturating_sub(height);
+        max_scroll = max_scroll.max(pane_scroll);
+        let end = rows.len() - app.output_view.scroll.min(pane_scroll);
+        let shown = &rows[end.saturating_sub(height)..end];
+        // Without wrapping, follow the end of the last row
+        let column = match shown.last() {
+            Some(row) if !app.output_view.wrap && app.output_view.scroll == 0 => {
+                row.width().saturating_sub(usize::from(pane.width))
+            }
+            _ => 0,
+        };
+        let text = Text::from(
+            shown
+                .iter()
+                .map(|row| Spans::from(row.as_str()))
+                .collect::<Vec<Spans>>(),
+        );
+        let paragraph = Paragraph::new(text)
+            .style(
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            )
+            .scroll((0, u16::try_from(column).unwrap_or(u16::MAX)));
+        f.render_widget(paragraph, pane);
+    }
+    app.output_view.page.set((height.max(1), max_scroll));
 }
 
-fn ui_details<B: Backend>(f: &mut Frame<B>, app: &App, chunks: &[Rect]) {
+fn ui_details<C: Cell, B: Backend>(f: &mut Frame<B>, app: &App<C>, chunks: &[Rect]) {
     let detail_chunks = Layout::default()
         .direction(Direction::Horizontal)
         .margin(0)
@@ -423,7 +1025,7 @@ fn ui_details<B: Backend>(f: &mut Frame<B>, app: &App, chunks: &[Rect]) {
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title("-::[Jumps]::-")
+                .title(app.get_jumps_title())
                 .title_alignment(Alignment::Center),
         );
     f.render_widget(jump_block, detail_chunks[0]);
@@ -438,14 +1040,14 @@ fn ui_details<B: Backend>(f: &mut Frame<B>, app: &App, chunks: &[Rect]) {
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title("-::[Code]::-")
+                .title(app.get_code_title())
                 .title_alignment(Alignment::Center),
         )
         .scroll((scroll, 0))
         .wrap(Wrap { trim: false });
     f.render_widget(code_block, detail_chunks[1]);
 
-    let freq_list = List::new(App::list_frequencies())
+    let freq_list = List::new(App::<C>::list_frequencies())
         .block(
             Block::default()
                 .title(":[Frequency]:")
@@ -460,7 +1062,7 @@ fn ui_details<B: Backend>(f: &mut Frame<B>, app: &App, chunks: &[Rect]) {
     f.render_stateful_widget(freq_list, detail_chunks[2], &mut app.get_freq_list_state());
 }
 
-fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
+fn ui<C: Cell, B: Backend>(f: &mut Frame<B>, app: &App<C>) {
     // Wrapping block for a group
     // Just draw the block and the group on the same area and build the group
     // with at least a margin of 1
@@ -479,51 +1081,54 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
         .margin(1)
         .constraints(
             [
-                Constraint::Percentage(50),
-                Constraint::Length(3),
+                Constraint::Percentage(30),
+                Constraint::Percentage(20),
                 Constraint::Min(10),
-                Constraint::Length(3),
+                Constraint::Length(4),
             ]
             .as_ref(),
         )
         .split(f.size());
 
-    let cell_count = (chunks[0].width - 2) / 6;
-    let (rdata, position) = app.get_ribbon(cell_count.into());
-    let mut ribbon_spans: Vec<Span<'_>> = rdata
-        .iter()
-        .map(|x| Span::raw(format!(" {x:03} |")))
-        .collect::<Vec<Span>>();
-    ribbon_spans.insert(0, Span::raw("|"));
-    let ribbon_block = Paragraph::new(Text::from(vec![
-        Spans::from(ribbon_spans),
-        Spans::from(
-            (0..cell_count)
-                .map(|x| {
-                    if usize::from(x) == position % usize::from(cell_count) {
-                        "   ^  "
-                    } else {
-                        "      "
-                    }
-                })
-                .map(Span::raw)
-                .collect::<Vec<Span>>(),
-        ),
-    ]))
-    .block(Block::default().title("Ribbons").borders(Borders::ALL))
-    .alignment(Alignment::Center);
-    f.render_widget(ribbon_block, chunks[0]);
+    if app.output_view.fullscreen {
+        // Take the place of every other panel
+        let area = chunks[0].union(chunks[2]);
+        ui_output(f, app, area);
+    } else {
+        ui_ribbon(f, app, chunks[0]);
 
-    ui_io(f, app, &chunks);
+        ui_error(f, app, chunks[0]);
 
-    ui_details(f, app, &chunks);
+        ui_io(f, app, &chunks);
 
-    let help_block = Paragraph::new(format!("Q: Quit    S: Step    Space: {}    R: Reset\nUp: Lower Frequency    Down: Increase Frequency", if app.running { "Pause"  } else { "Start" }))
-        .block(Block::default()
-            .borders(Borders::TOP)
-            .title(Span::styled("Keys", Style::default().fg(Color::Red).add_modifier(Modifier::ITALIC)))
-            .title_alignment(Alignment::Center)
-            .border_type(BorderType::Plain))
+        ui_details(f, app, &chunks);
+    }
+
+    let help = if app.edition_mode == InputEditionMode::Editing {
+        "Esc: Stop Typing    Left/Right/Home/End: Move    Backspace/Delete: Erase    Ctrl-C: Quit\nEnter: New Line    Any other key or pasted text is added to the input".to_string()
+    } else {
+        format!("Q: Quit    S: Step    Space: {}    R: Reset    I: Edit Input    E: Output Encoding\nUp: Lower Frequency    Down: Increase Frequency    D: Save Output and Ribbons\nPgUp/PgDn: Scroll Output    W: Wrap Output    X: Hex Dump    O: Full Screen Output", if app.running { "Pause"  } else { "Start" })
+    };
+    let mut title = vec![Span::styled(
+        "Keys",
+        Style::default()
+            .fg(Color::Red)
+            .add_modifier(Modifier::ITALIC),
+    )];
+    if let Some(message) = &app.message {
+        title.push(Span::styled(
+            format!(" ({message})"),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    let help_block = Paragraph::new(help)
+        .block(
+            Block::default()
+                .borders(Borders::TOP)
+                .title(Spans::from(title))
+                .title_alignment(Alignment::Center)
+                .border_type(BorderType::Plain),
+        )
         .style(Style::default().fg(Color::White).bg(Color::Black))
         .alignment(Alignment::Center);
     f.render_widget(help_block, chunks[3]);
@@ -1,32 +1,663 @@
 //! Runner for the moostar visualizer
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::iter::Peekable;
+use std::fmt::Write as _;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 
+pub mod ast;
+pub mod cell;
+
+use ast::{Node, NodeKind};
+use cell::Cell;
+
+/// The different kinds of problems found while loading a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MooErrorKind {
+    /// An identifier does not start with a lowercase letter
+    InvalidIdentifierStart,
+    /// The source ended where an identifier was expected
+    EmptyIdentifier,
+    /// A function declaration header is missing one of `)`, `:` or `{`
+    MalformedHeader(char),
+    /// A `}` was found outside of any function definition
+    UnexpectedFunctionEnd,
+    /// A function call is not terminated by `;`
+    MissingSemicolon,
+    /// A function definition is never closed by `}`
+    UnterminatedDefinition,
+    /// A `[` has no matching `]`
+    UnmatchedOpenLoop,
+    /// A `]` has no matching `[`
+    UnmatchedCloseLoop,
+    /// A `[` is still open when its function definition ends
+    LoopOpenAtFunctionEnd,
+    /// A `]` closes a loop opened outside of its function definition
+    LoopClosedAcrossFunction,
+    /// A function is called but never defined, with the closest defined name
+    UndefinedFunction(String, Option<String>),
+    /// A function is defined again without being marked as an override
+    DuplicateDefinition(String),
+    /// A block comment is never closed by `*/`
+    UnterminatedComment,
+    /// An include directive is not written `@include "path";`
+    MalformedInclude,
+    /// An include directive appears inside of a function definition
+    IncludeInsideDefinition,
+    /// An included file could not be read, with the reason why
+    IncludeFailed(PathBuf, String),
+    /// A file ends up including itself
+    IncludeCycle(PathBuf),
+    /// An included file has code outside of its function definitions
+    CodeOutsideDefinition,
+}
+
+impl std::fmt::Display for MooErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidIdentifierStart => {
+                write!(f, "First character in identifier is not lowercase")
+            }
+            Self::EmptyIdentifier => write!(f, "Empty identifier"),
+            Self::MalformedHeader(guard) => {
+                write!(f, "Expected '{guard}' after function declaration header")
+            }
+            Self::UnexpectedFunctionEnd => write!(f, "Ended an unknown function definition"),
+            Self::MissingSemicolon => write!(f, "Expected ';' after function call identifier"),
+            Self::UnterminatedDefinition => write!(f, "Function definition is never closed"),
+            Self::UnmatchedOpenLoop => write!(f, "Unmatched '['"),
+            Self::UnmatchedCloseLoop => write!(f, "Unmatched ']'"),
+            Self::LoopOpenAtFunctionEnd => {
+                write!(
+                    f,
+                    "Loop is still open at the end of its function definition"
+                )
+            }
+            Self::LoopClosedAcrossFunction => {
+                write!(
+                    f,
+                    "Loop is closed inside a function definition it was not opened in"
+                )
+            }
+            Self::UndefinedFunction(name, _) => write!(f, "Call to undefined function '{name}'"),
+            Self::DuplicateDefinition(name) => {
+                write!(f, "Function '{name}' is defined more than once")
+            }
+            Self::UnterminatedComment => write!(f, "Block comment is never closed"),
+            Self::MalformedInclude => write!(f, "Expected '@include \"path\";'"),
+            Self::IncludeInsideDefinition => {
+                write!(
+                    f,
+                    "Files cannot be included inside of a function definition"
+                )
+            }
+            Self::IncludeFailed(path, reason) => {
+                write!(f, "Could not include '{}': {reason}", path.display())
+            }
+            Self::IncludeCycle(path) => {
+                write!(f, "Including '{}' would include it again", path.display())
+            }
+            Self::CodeOutsideDefinition => {
+                write!(f, "Included files may only hold function definitions")
+            }
+        }
+    }
+}
+
+/// Number of columns between two tab stops
+pub const TAB_WIDTH: usize = 4;
+
+/// Number of columns taken by `c` when displayed at `column`
+///
+/// Tabs reach up to the next tab stop, wide characters take two columns and
+/// control characters take none.
+#[must_use]
+pub fn char_width(c: char, column: usize) -> usize {
+    if c == '\t' {
+        TAB_WIDTH - column % TAB_WIDTH
+    } else {
+        c.width().unwrap_or(0)
+    }
+}
+
+/// Turn a line of code into what it looks like on screen, expanding tabs
+/// into spaces and dropping control characters
+#[must_use]
+pub fn display_line(line: &str) -> String {
+    let mut column: usize = 0;
+    let mut displayed: String = String::new();
+    for c in line.chars() {
+        let width = char_width(c, column);
+        if c == '\t' {
+            displayed.extend(std::iter::repeat_n(' ', width));
+        } else if !c.is_control() {
+            displayed.push(c);
+        }
+        column += width;
+    }
+    displayed
+}
+
+/// A file a program is loaded from
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// Path of the file, relative to the including file for included ones
+    pub path: PathBuf,
+    /// Contents of the file
+    pub text: String,
+}
+
+impl SourceFile {
+    /// Find where the byte at `offset` is displayed
+    ///
+    /// Returns the line number (from 1), the offset of the start of that line
+    /// and the display column of `offset` in it (from 0).
+    #[must_use]
+    pub fn locate(&self, offset: usize) -> (usize, usize, usize) {
+        let mut line_number: usize = 1;
+        let mut line_start: usize = 0;
+        let mut column: usize = 0;
+        for (pos, c) in self.text.char_indices() {
+            if pos >= offset {
+                break;
+            }
+            if c == '\n' {
+                line_number += 1;
+                line_start = pos + 1;
+                column = 0;
+            } else {
+                column += char_width(c, column);
+            }
+        }
+        (line_number, line_start, column)
+    }
+}
+
+/// Location of a piece of code
+///
+/// Positions are byte offsets into the text of the file, which always fall
+/// on character boundaries; use [`SourceFile::locate`] to find the matching
+/// display columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Index of the [`SourceFile`] the code comes from
+    pub file: usize,
+    /// Position of the code in its file, in bytes
+    pub start: usize,
+    /// Length of the code, in bytes
+    pub len: usize,
+}
+
+impl Span {
+    fn new(file: usize, start: usize, len: usize) -> Self {
+        Self { file, start, len }
+    }
+}
+
+/// A diagnostic pointing at the part of the source that could not be loaded
 #[derive(Debug, Clone)]
 pub struct MooError {
-    message: String,
+    kind: MooErrorKind,
+    /// Location of the offending code
+    span: Span,
+    /// Location of related code found earlier
+    previous: Option<Span>,
 }
 
 impl MooError {
-    fn new(msg: &str) -> Self {
+    fn new(kind: MooErrorKind, span: Span) -> Self {
         Self {
-            message: msg.into(),
+            kind,
+            span,
+            previous: None,
         }
     }
+
+    /// What went wrong
+    #[must_use]
+    pub fn kind(&self) -> &MooErrorKind {
+        &self.kind
+    }
+
+    /// Where it went wrong
+    #[must_use]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Attach the span of an earlier piece of code this diagnostic relates to
+    fn with_previous(mut self, previous: Span) -> Self {
+        self.previous = Some(previous);
+        self
+    }
+
+    /// Render the diagnostic with the offending line of its source file and
+    /// a caret under the span
+    #[must_use]
+    pub fn render(&self, sources: &[SourceFile]) -> String {
+        let (mut report, gutter) = render_snippet(&sources[self.span.file], self.span);
+        report.insert_str(0, &format!("error: {}\n", self.kind));
+        if let Some(previous) = self.previous {
+            let (snippet, _) = render_snippet(&sources[previous.file], previous);
+            report.push_str("note: previous definition is here\n");
+            report.push_str(&snippet);
+        }
+        // Writing to a string never fails
+        let _ = match &self.kind {
+            MooErrorKind::UndefinedFunction(_, Some(suggestion)) => {
+                writeln!(report, "{gutter} = help: did you mean '{suggestion}'?")
+            }
+            MooErrorKind::DuplicateDefinition(name) => writeln!(
+                report,
+                "{gutter} = help: write '(!{name}):{{' to override the previous definition"
+            ),
+            _ => Ok(()),
+        };
+        report
+    }
+}
+
+/// Render the line of `source` holding the start of `span` with a caret under
+/// the span, along with the width of the gutter
+fn render_snippet(source: &SourceFile, span: Span) -> (String, String) {
+    // Find the line and columns of the span
+    let (line_number, line_start, column) = source.locate(span.start);
+    let line: &str = source.text[line_start..]
+        .split('\n')
+        .next()
+        .unwrap_or_default();
+    // Don't let the caret run past the end of the line
+    let (_, _, end_column) = source.locate((span.start + span.len).min(line_start + line.len()));
+    let len = end_column.saturating_sub(column).max(1);
+    let gutter = " ".repeat(line_number.to_string().len());
+    let snippet = format!(
+        "{gutter}--> {}, line {line_number}, column {}\n{gutter} |\n{line_number} | {}\n{gutter} | {}{}\n",
+        source.path.display(),
+        column + 1,
+        display_line(line),
+        " ".repeat(column),
+        "^".repeat(len),
+    );
+    (snippet, gutter)
 }
 
 impl std::fmt::Display for MooError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
+        write!(f, "{}", self.kind)
     }
 }
 
 impl Error for MooError {}
 
-#[derive(Clone, Debug)]
+/// Every diagnostic found while loading a program, along with the files they
+/// point into
+#[derive(Debug, Clone)]
+pub struct MooErrors {
+    errors: Vec<MooError>,
+    sources: Vec<SourceFile>,
+}
+
+impl MooErrors {
+    /// Render all diagnostics one after the other
+    #[must_use]
+    pub fn render(&self) -> String {
+        self.errors
+            .iter()
+            .map(|error| error.render(&self.sources))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl std::fmt::Display for MooErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for error in &self.errors {
+            writeln!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for MooErrors {}
+
+/// One of the two ribbons a program works on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ribbon {
+    Data,
+    Meta,
+}
+
+impl std::fmt::Display for Ribbon {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Data => write!(f, "data"),
+            Self::Meta => write!(f, "meta"),
+        }
+    }
+}
+
+/// Shape of the ribbons
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TapeMode {
+    /// Cells start at address 0, and moving left of it is an error
+    #[default]
+    Bounded,
+    /// Cells go on in both directions, with negative addresses on the left
+    Infinite,
+    /// A ring of cells, where moving past either end comes back on the other
+    Circular(NonZeroUsize),
+}
+
+impl std::fmt::Display for TapeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bounded => write!(f, "bounded"),
+            Self::Infinite => write!(f, "infinite"),
+            Self::Circular(cells) => write!(f, "circular, {cells} cells"),
+        }
+    }
+}
+
+/// What `+` and `-` do past the largest and smallest values of a cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    /// Go back to the other end
+    #[default]
+    Wrapping,
+    /// Stay on the largest or smallest value
+    Saturating,
+    /// Stop the program with an error
+    Trapping,
+}
+
+/// How to turn the bytes written by a program into text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Characters encoded in UTF-8, possibly over several bytes
+    #[default]
+    Utf8,
+    /// One character per byte
+    Latin1,
+    /// Decimal value of every byte
+    Decimal,
+    /// Hexadecimal value of every byte
+    Hex,
+}
+
+impl OutputMode {
+    /// Mode to switch to after this one
+    #[must_use]
+    pub fn next(self) -> Self {
+        match self {
+            Self::Utf8 => Self::Latin1,
+            Self::Latin1 => Self::Decimal,
+            Self::Decimal => Self::Hex,
+            Self::Hex => Self::Utf8,
+        }
+    }
+
+    /// Turn bytes into text
+    ///
+    /// In UTF-8, invalid sequences are replaced with `U+FFFD`, but a
+    /// sequence cut short at the end is left out, as the rest of it may
+    /// still come.
+    #[must_use]
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Utf8 => {
+                let mut text = String::new();
+                let mut rest = bytes;
+                loop {
+                    match std::str::from_utf8(rest) {
+                        Ok(valid) => {
+                            text.push_str(valid);
+                            break;
+                        }
+                        Err(error) => {
+                            let (valid, invalid) = rest.split_at(error.valid_up_to());
+                            text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                            let Some(len) = error.error_len() else {
+                                break;
+                            };
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &invalid[len..];
+                        }
+                    }
+                }
+                text
+            }
+            Self::Latin1 => bytes.iter().copied().map(char::from).collect(),
+            Self::Decimal => bytes
+                .iter()
+                .map(u8::to_string)
+                .collect::<Vec<String>>()
+                .join(" "),
+            Self::Hex => bytes
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<Vec<String>>()
+                .join(" "),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Utf8 => write!(f, "UTF-8"),
+            Self::Latin1 => write!(f, "Latin-1"),
+            Self::Decimal => write!(f, "decimal"),
+            Self::Hex => write!(f, "hex"),
+        }
+    }
+}
+
+/// Deepest call stack allowed unless told otherwise
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+/// Steps between two looks at the clock when running with a time limit
+const STEPS_PER_CLOCK_CHECK: u64 = 1024;
+
+/// Resources a program may use before it is stopped
+///
+/// Only the depth of the call stack is limited by default, to
+/// [`DEFAULT_MAX_CALL_DEPTH`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Most steps the program may run
+    pub steps: Option<u64>,
+    /// Longest the program may run for, counted from its first step
+    ///
+    /// The clock is only looked at every so many steps, and keeps going
+    /// between steps, waiting for input included, so this is meant for
+    /// programs run at full speed.
+    pub time: Option<Duration>,
+    /// Most cells the program may write to on each ribbon
+    pub cells: Option<usize>,
+    /// Deepest calls may go
+    pub depth: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            steps: None,
+            time: None,
+            cells: None,
+            depth: Some(DEFAULT_MAX_CALL_DEPTH),
+        }
+    }
+}
+
+/// An entry of the stacks of a running program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frame<'r> {
+    /// A loop being run, with the span of its `[`
+    Loop { start: Span },
+    /// A function being run, with its name and the span of the call
+    ///
+    /// `collapsed` counts the tail calls that reused the frame, the function
+    /// being the one called last.
+    Call {
+        function: &'r str,
+        call: Span,
+        collapsed: usize,
+    },
+}
+
+/// A loop being run
+struct LoopFrame {
+    /// Position of the `[`
+    start: usize,
+    /// Depth of the call stack when the loop was entered
+    calls: usize,
+}
+
+/// A function being run
+struct CallFrame {
+    function: usize,
+    /// Position of the call, to return after it
+    call: usize,
+    /// Tail calls that reused the frame
+    collapsed: usize,
+}
+
+/// What a step of the program did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// An instruction was run
+    Ran,
+    /// The program is stuck on a `,` until more input is provided
+    Waiting,
+    /// The program is over
+    Halted,
+}
+
+/// The different ways a running program can fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    /// The pointer moved left of the first cell of a ribbon
+    TapeUnderflow(Ribbon),
+    /// A cell was incremented past its largest value
+    CellOverflow(Ribbon),
+    /// A cell was decremented past its smallest value
+    CellUnderflow(Ribbon),
+    /// A `]` was met without any loop to go back to
+    UnmatchedLoopEnd,
+    /// A function without a body was called
+    UndefinedFunction(String),
+    /// A call went past the maximum depth of the call stack
+    StackOverflow(usize),
+    /// The program ran for as many steps as allowed
+    StepLimit(u64),
+    /// The program ran for as long as allowed
+    TimeLimit(Duration),
+    /// A new cell was written to on a ribbon holding as many as allowed
+    CellLimit(Ribbon, usize),
+}
+
+impl RuntimeErrorKind {
+    /// Whether the program went past one of its [`Limits`], rather than
+    /// doing something wrong
+    #[must_use]
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            Self::StackOverflow(_) | Self::StepLimit(_) | Self::TimeLimit(_) | Self::CellLimit(..)
+        )
+    }
+}
+
+impl std::fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TapeUnderflow(ribbon) => {
+                write!(f, "Moved left of the first cell of the {ribbon} ribbon")
+            }
+            Self::CellOverflow(ribbon) => {
+                write!(
+                    f,
+                    "Incremented a cell of the {ribbon} ribbon past its largest value"
+                )
+            }
+            Self::CellUnderflow(ribbon) => {
+                write!(
+                    f,
+                    "Decremented a cell of the {ribbon} ribbon past its smallest value"
+                )
+            }
+            Self::UnmatchedLoopEnd => write!(f, "Closing a loop that was never entered"),
+            Self::UndefinedFunction(name) => write!(f, "Call to undefined function '{name}'"),
+            Self::StackOverflow(depth) => {
+                write!(f, "Call stack went past its maximum depth of {depth}")
+            }
+            Self::StepLimit(steps) => write!(f, "Ran for the {steps} steps allowed"),
+            Self::TimeLimit(time) => write!(f, "Ran for the {time:?} allowed"),
+            Self::CellLimit(ribbon, cells) => {
+                write!(
+                    f,
+                    "Wrote to more than the {cells} cells allowed on the {ribbon} ribbon"
+                )
+            }
+        }
+    }
+}
+
+/// An error met while running a program, at the instruction that caused it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    kind: RuntimeErrorKind,
+    span: Span,
+}
+
+impl RuntimeError {
+    #[must_use]
+    pub fn kind(&self) -> &RuntimeErrorKind {
+        &self.kind
+    }
+
+    #[must_use]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Render the error along with the instruction that caused it, `sources`
+    /// being the files of the program
+    #[must_use]
+    pub fn render(&self, sources: &[SourceFile]) -> String {
+        let (snippet, _) = render_snippet(&sources[self.span.file], self.span);
+        format!("error: {}\n{snippet}", self.kind)
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// Number of single character edits needed to go from `a` to `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current: Vec<usize> = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[derive(Clone, Copy, Debug)]
 enum MooInst {
     Plus,
     Minus,
@@ -36,328 +667,797 @@ enum MooInst {
     Out,
     OpenLoop,
     CloseLoop,
-    Nop(char),
+    Nop,
+    Comment,
+    Directive,
     Call(usize),
-    FuncStart(usize),
-    FuncEnd(usize),
+    FuncStart,
+    FuncEnd,
     MetaJump,
     Halt,
 }
 
-fn fetch_identifier(
-    chars: &mut Peekable<std::str::Chars<'_>>,
-) -> Result<(String, usize), Box<dyn Error>> {
-    let mut identifier: String = String::new();
-    let mut eaten: usize = 0;
-    let mut stage: usize = 0; // 0 ws before, 1 eat identifier, 2 ws after
-    while chars.peek().is_some() {
-        let c = chars.peek().unwrap();
-        match stage {
-            0 => {
-                if !c.is_whitespace() {
-                    stage += 1;
-                    // Is it a lower ascii ?
-                    if c.is_ascii_lowercase() {
-                        continue;
+/// What `,` does once the input has been entirely consumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofPolicy {
+    /// Write a zero in the current cell
+    Zero,
+    /// Write the largest value a cell can hold, such as 255 for bytes
+    Max,
+    /// Leave the current cell as it is
+    Unchanged,
+    /// Stay on the `,` until more input is provided
+    #[default]
+    Wait,
+}
+
+/// Error raised when starting a program from a function it does not define
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndefinedEntry {
+    name: String,
+    suggestion: Option<String>,
+}
+
+impl std::fmt::Display for UndefinedEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cannot start from undefined function '{}'", self.name)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean '{suggestion}'?")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for UndefinedEntry {}
+
+/// Names of the functions defined right inside of `body`, including inside of
+/// its loops
+fn helpers(body: &[Node]) -> Vec<&str> {
+    body.iter()
+        .flat_map(|node| match &node.kind {
+            NodeKind::Function { name, .. } => vec![name.as_str()],
+            NodeKind::Loop { body, .. } => helpers(body),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Suggest the closest candidate to `name`, if it is close enough
+fn closest<'c>(name: &str, candidates: impl Iterator<Item = &'c String>) -> Option<String> {
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= (name.len() / 3).max(1) && *distance < name.len())
+        .min()
+        .map(|(_, candidate)| candidate.clone())
+}
+
+type SpannedInstruction = (MooInst, Span);
+type MethodIndex = HashMap<usize, usize>;
+/// Names of functions and their codes
+type Scope = HashMap<String, usize>;
+
+/// State shared by all the files of a program while it is being loaded
+#[derive(Default)]
+struct Loader {
+    sources: Vec<SourceFile>,
+    /// Canonical paths of the files being processed, outermost first
+    including: Vec<PathBuf>,
+    /// Canonical paths of every file processed so far
+    included: HashSet<PathBuf>,
+    program: Vec<SpannedInstruction>,
+    /// Name of every function, by code
+    method_names: Vec<String>,
+    /// Functions visible from everywhere
+    globals: Scope,
+    /// Helpers of the functions being processed, outermost first
+    locals: Vec<Scope>,
+    method_index: MethodIndex,
+}
+
+impl Loader {
+    /// Process the main file of a program
+    fn load(&mut self, program: &str, path: &Path) -> Result<(), MooError> {
+        if let Ok(canonical) = std::fs::canonicalize(path) {
+            self.including.push(canonical.clone());
+            self.included.insert(canonical);
+        }
+        self.sources.push(SourceFile {
+            path: path.to_path_buf(),
+            text: program.to_string(),
+        });
+        self.process(0)?;
+        let end = Span::new(0, program.len(), 1);
+        self.program.push((MooInst::Halt, end));
+        Ok(())
+    }
+
+    /// Process the file included by the directive at `span`, unless it was
+    /// already included before
+    fn include(&mut self, relative: &Path, span: Span) -> Result<(), MooError> {
+        // Paths are relative to the including file
+        let path = match self.sources[span.file].path.parent() {
+            Some(directory) => directory.join(relative),
+            None => relative.to_path_buf(),
+        };
+        let failed = |e: std::io::Error| {
+            MooError::new(
+                MooErrorKind::IncludeFailed(relative.to_path_buf(), e.to_string()),
+                span,
+            )
+        };
+        let canonical = std::fs::canonicalize(&path).map_err(failed)?;
+        if self.including.contains(&canonical) {
+            return Err(MooError::new(
+                MooErrorKind::IncludeCycle(relative.to_path_buf()),
+                span,
+            ));
+        }
+        if !self.included.insert(canonical.clone()) {
+            return Ok(());
+        }
+        let text = std::fs::read_to_string(&path).map_err(failed)?;
+        self.sources.push(SourceFile { path, text });
+        self.including.push(canonical);
+        self.process(self.sources.len() - 1)?;
+        self.including.pop();
+        Ok(())
+    }
+
+    /// Allocate a code for a new function
+    fn new_method(&mut self, ident: &str) -> usize {
+        self.method_names.push(ident.to_string());
+        self.method_names.len() - 1
+    }
+
+    /// Obtain the code of the function a call to `ident` refers to
+    ///
+    /// The helpers of the innermost functions come first, and global
+    /// functions get a code even before they are defined.
+    fn method_code(&mut self, ident: &str) -> usize {
+        if let Some(&c) = self
+            .locals
+            .iter()
+            .rev()
+            .chain(std::iter::once(&self.globals))
+            .find_map(|scope| scope.get(ident))
+        {
+            c
+        } else {
+            let n = self.new_method(ident);
+            self.globals.insert(ident.to_string(), n);
+            n
+        }
+    }
+
+    /// Start the definition of a function whose header is at `span`
+    fn define(&mut self, ident: String, overrides: bool, span: Span) -> Result<usize, MooError> {
+        // Do we know about the function ?
+        let function_code = match self.locals.last() {
+            Some(scope) => scope[&ident],
+            None => self.method_code(&ident),
+        };
+        // Only overrides may replace an existing definition
+        if let Some(&previous) = self.method_index.get(&function_code) {
+            if !overrides {
+                return Err(
+                    MooError::new(MooErrorKind::DuplicateDefinition(ident), span)
+                        .with_previous(self.program[previous].1),
+                );
+            }
+        }
+        // Insert a code
+        self.method_index.insert(function_code, self.program.len());
+        self.program.push((MooInst::FuncStart, span));
+        Ok(function_code)
+    }
+
+    /// Process one of the files of the program
+    fn process(&mut self, file: usize) -> Result<(), MooError> {
+        let program = ast::parse(&self.sources[file].text, file)?;
+        // Only definitions may be shared between files
+        if file > 0 {
+            for node in &program.nodes {
+                match node.kind {
+                    NodeKind::Function { .. }
+                    | NodeKind::Include(_)
+                    | NodeKind::LineComment(_)
+                    | NodeKind::BlockComment(_)
+                    | NodeKind::Text(_) => {}
+                    _ => {
+                        return Err(MooError::new(
+                            MooErrorKind::CodeOutsideDefinition,
+                            node.span,
+                        ));
                     }
-                    return Err(Box::new(MooError::new(
-                        "First character in identifier is not lowercase",
-                    )));
                 }
             }
-            1 => {
-                if c.is_ascii_alphanumeric() || *c == '_' {
-                    identifier.push(*c);
-                } else {
-                    stage += 1;
-                    continue;
+        }
+        self.lower(&program.nodes, None, &mut Vec::new())
+    }
+
+    /// Turn nodes into instructions, `function` being the code of the
+    /// function they are defined in
+    ///
+    /// Helpers defined inside of a function are not lowered right away but
+    /// put in `nested`, so that they end up after the body of their parent.
+    fn lower<'n>(
+        &mut self,
+        nodes: &'n [Node],
+        function: Option<usize>,
+        nested: &mut Vec<&'n Node>,
+    ) -> Result<(), MooError> {
+        for node in nodes {
+            let span = node.span;
+            match &node.kind {
+                NodeKind::Plus => self.program.push((MooInst::Plus, span)),
+                NodeKind::Minus => self.program.push((MooInst::Minus, span)),
+                NodeKind::Left => self.program.push((MooInst::Left, span)),
+                NodeKind::Right => self.program.push((MooInst::Right, span)),
+                NodeKind::In => self.program.push((MooInst::In, span)),
+                NodeKind::Out => self.program.push((MooInst::Out, span)),
+                NodeKind::MetaToggle => self.program.push((MooInst::MetaJump, span)),
+                NodeKind::Loop { body, end } => {
+                    self.program.push((MooInst::OpenLoop, span));
+                    self.lower(body, function, nested)?;
+                    self.program.push((MooInst::CloseLoop, *end));
                 }
-            }
-            2 => {
-                if !c.is_whitespace() {
-                    break;
+                NodeKind::Function { .. } if function.is_some() => nested.push(node),
+                NodeKind::Function {
+                    name,
+                    overrides,
+                    body,
+                    end,
+                } => self.lower_function(name, *overrides, body, span, *end)?,
+                NodeKind::Call(name) => {
+                    let code = self.method_code(name);
+                    self.program.push((MooInst::Call(code), span));
+                }
+                NodeKind::Include(path) => {
+                    if function.is_some() {
+                        return Err(MooError::new(MooErrorKind::IncludeInsideDefinition, span));
+                    }
+                    self.program.push((MooInst::Directive, span));
+                    self.include(path, span)?;
                 }
+                // Comments are kept around so that they can be displayed
+                NodeKind::LineComment(_) | NodeKind::BlockComment(_) => {
+                    self.program.push((MooInst::Comment, span));
+                }
+                NodeKind::Text(_) => self.program.push((MooInst::Nop, span)),
             }
-            _ => unreachable!(),
         }
-        let _ = chars.next().unwrap();
-        eaten += 1;
+        Ok(())
     }
-    if identifier.is_empty() {
-        return Err(Box::new(MooError::new("Empty identifier")));
+
+    /// Turn a function definition into instructions, followed by the helpers
+    /// defined inside of it
+    fn lower_function(
+        &mut self,
+        name: &str,
+        overrides: bool,
+        body: &[Node],
+        span: Span,
+        end: Span,
+    ) -> Result<(), MooError> {
+        let code = self.define(name.to_string(), overrides, span)?;
+        // Helpers are only visible from inside of their parent, but from
+        // anywhere in there
+        let mut scope: Scope = HashMap::new();
+        for helper in helpers(body) {
+            if !scope.contains_key(helper) {
+                let helper_code = self.new_method(helper);
+                scope.insert(helper.to_string(), helper_code);
+            }
+        }
+        self.locals.push(scope);
+        let mut nested: Vec<&Node> = Vec::new();
+        self.lower(body, Some(code), &mut nested)?;
+        self.program.push((MooInst::FuncEnd, end));
+        for helper in nested {
+            if let NodeKind::Function {
+                name,
+                overrides,
+                body,
+                end,
+            } = &helper.kind
+            {
+                self.lower_function(name, *overrides, body, helper.span, *end)?;
+            }
+        }
+        self.locals.pop();
+        Ok(())
     }
-    Ok((identifier, eaten))
 }
 
-type SpannedInstruction = (MooInst, (usize, usize));
-type MethodIndex = HashMap<usize, usize>;
-
-/// A single Moostar Runner
-pub struct Runner {
-    /// Stack of iteration/call return pointers
-    return_positions: VecDeque<usize>,
+/// A single Moostar Runner, whose cells hold values of type `C`
+pub struct Runner<C: Cell = u8> {
+    /// Stacks of loops and calls being run, innermost last
+    loop_stack: Vec<LoopFrame>,
+    call_stack: Vec<CallFrame>,
+    /// Resources the program may use, and how much of them it used since the
+    /// last reset
+    limits: Limits,
+    steps: u64,
+    started: Option<Instant>,
+    /// Deepest the stacks went since the last reset
+    loop_high_water: usize,
+    call_high_water: usize,
     /// Pointers
-    pointer: usize,
-    meta_pointer: usize,
+    pointer: isize,
+    meta_pointer: isize,
     is_meta: bool,
     /// Ribbons
-    data_ribbon: HashMap<usize, u8>,
-    meta_ribbon: HashMap<usize, u8>,
+    data_ribbon: HashMap<isize, C>,
+    meta_ribbon: HashMap<isize, C>,
+    tape_mode: TapeMode,
+    overflow_mode: OverflowMode,
     /// Program
     program: Vec<SpannedInstruction>,
+    sources: Vec<SourceFile>,
     instruction_pointer: usize,
     halted: bool,
-    /// Input
-    input: String,
+    /// Input, and how much of it was consumed already
+    input: Vec<u8>,
+    input_position: usize,
+    eof_policy: EofPolicy,
+    /// Whether the last step stayed on a `,` for lack of input
+    waiting: bool,
     /// Output,
-    output: String,
+    output: Vec<u8>,
     /// Method management
     method_index: MethodIndex,
+    /// Name of every function, by code
+    method_names: Vec<String>,
+    /// Functions that can be called from anywhere, by name
+    functions: Scope,
+    /// Function the program starts from, if not from the top level code
+    entry_point: Option<usize>,
 }
 
-impl Runner {
-    pub fn new(program: &str) -> Result<Self, Box<dyn Error>> {
+impl<C: Cell> Runner<C> {
+    /// Load a program, given the path it was read from to find the files it
+    /// includes
+    ///
+    /// # Errors
+    ///
+    /// Returns every diagnostic found if the program or one of the files it
+    /// includes cannot be loaded.
+    pub fn new(program: &str, path: &Path) -> Result<Self, MooErrors> {
         // Process
-        let (instr, method_index) = Self::process(program)?;
-        // Find the index of the first non-defining instruction
-        let mut silencer: bool = true;
-        let mut idx: usize = 0;
-        for (pos, inst) in instr.iter().enumerate() {
-            match inst.0 {
-                MooInst::FuncStart(_) => {
-                    silencer = true;
-                }
-                MooInst::FuncEnd(_) => {
-                    silencer = false;
-                }
-                MooInst::Nop(_) => {}
-                _ => {
-                    if !silencer {
-                        idx = pos;
-                        break;
-                    }
-                }
-            }
+        let mut loader = Loader::default();
+        if let Err(error) = loader.load(program, path) {
+            return Err(MooErrors {
+                errors: vec![error],
+                sources: loader.sources,
+            });
         }
-        Ok(Self {
-            return_positions: VecDeque::new(),
+        let Loader {
+            sources,
+            program: instr,
+            method_index,
+            method_names,
+            globals,
+            ..
+        } = loader;
+        if let Err(errors) = Self::resolve_calls(&instr, &method_index, &method_names, &globals) {
+            return Err(MooErrors { errors, sources });
+        }
+        let mut runner = Self {
+            loop_stack: Vec::new(),
+            call_stack: Vec::new(),
+            limits: Limits::default(),
+            steps: 0,
+            started: None,
+            loop_high_water: 0,
+            call_high_water: 0,
             pointer: 0,
             meta_pointer: 0,
             is_meta: false,
             halted: false,
             data_ribbon: HashMap::new(),
             meta_ribbon: HashMap::new(),
+            tape_mode: TapeMode::default(),
+            overflow_mode: OverflowMode::default(),
             program: instr,
-            instruction_pointer: idx,
-            input: String::new(),
-            output: String::new(),
+            sources,
+            instruction_pointer: 0,
+            input: Vec::new(),
+            input_position: 0,
+            eof_policy: EofPolicy::default(),
+            waiting: false,
+            output: Vec::new(),
             method_index,
-        })
+            entry_point: globals.get("main").copied(),
+            method_names,
+            functions: globals,
+        };
+        runner.reset();
+        Ok(runner)
+    }
+
+    /// Start the program from the function called `name`, or from the top
+    /// level code when there is none, and reset it
+    ///
+    /// Programs defining a `main` function start from it by default.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no function called `name` can be called from the
+    /// top level code.
+    pub fn set_entry_point(&mut self, name: Option<&str>) -> Result<(), UndefinedEntry> {
+        self.entry_point = match name {
+            Some(name) => match self.functions.get(name) {
+                Some(&code) => Some(code),
+                None => {
+                    return Err(UndefinedEntry {
+                        name: name.to_string(),
+                        suggestion: closest(name, self.functions.keys()),
+                    })
+                }
+            },
+            None => None,
+        };
+        self.reset();
+        Ok(())
+    }
+
+    /// Obtain the name of the function the program starts from, if any
+    #[must_use]
+    pub fn get_entry_point(&self) -> Option<&str> {
+        self.entry_point
+            .map(|code| self.method_names[code].as_str())
     }
 
     pub fn reset(&mut self) {
-        self.return_positions = VecDeque::new();
+        self.loop_stack = Vec::new();
+        self.call_stack = Vec::new();
+        self.loop_high_water = 0;
+        self.call_high_water = 0;
+        self.steps = 0;
+        self.started = None;
         self.pointer = 0;
         self.meta_pointer = 0;
         self.is_meta = false;
         self.halted = false;
         self.data_ribbon = HashMap::new();
         self.meta_ribbon = HashMap::new();
-        let mut silencer: bool = true;
-        let mut idx: usize = 0;
-        for (pos, inst) in self.program.iter().enumerate() {
-            match inst.0 {
-                MooInst::FuncStart(_) => {
-                    silencer = true;
-                }
-                MooInst::FuncEnd(_) => {
-                    silencer = false;
-                }
-                MooInst::Nop(_) => {}
-                _ => {
-                    if !silencer {
-                        idx = pos;
-                        break;
-                    }
-                }
-            }
+        self.instruction_pointer = match self.entry_point {
+            Some(code) => self.method_index[&code] + 1,
+            None => 0,
+        };
+        self.skip_inert();
+        // Keep the input around to replay it
+        self.input_position = 0;
+        self.waiting = false;
+        self.output = Vec::new();
+    }
+
+    /// Make sure that every called function is defined somewhere
+    fn resolve_calls(
+        program: &[SpannedInstruction],
+        method_index: &MethodIndex,
+        method_names: &[String],
+        globals: &Scope,
+    ) -> Result<(), Vec<MooError>> {
+        // Helpers are always defined, and cannot be suggested from outside
+        let defined: Vec<&String> = globals
+            .iter()
+            .filter(|(_, code)| method_index.contains_key(code))
+            .map(|(name, _)| name)
+            .collect();
+        let errors: Vec<MooError> = program
+            .iter()
+            .filter_map(|(inst, span)| match inst {
+                MooInst::Call(code) if !method_index.contains_key(code) => Some((*code, *span)),
+                _ => None,
+            })
+            .map(|(code, span)| {
+                let name = method_names[code].clone();
+                let suggestion = closest(&name, defined.iter().copied());
+                MooError::new(MooErrorKind::UndefinedFunction(name, suggestion), span)
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
-        self.instruction_pointer = idx;
-        self.input = String::new();
-        self.output = String::new();
     }
 
-    fn process(program: &str) -> Result<(Vec<SpannedInstruction>, MethodIndex), Box<dyn Error>> {
-        let mut method_lookup: HashMap<String, usize> = HashMap::new();
-        let mut method_index: MethodIndex = HashMap::new();
-        let mut program_out: Vec<(MooInst, (usize, usize))> = Vec::new();
-        let mut method_fetcher = program.chars().peekable();
-        let mut pos = 0;
-        let mut current_function_definition: Option<usize> = None;
-        while method_fetcher.peek().is_some() {
-            let c = method_fetcher.next().unwrap();
-            match c {
-                '+' => {
-                    program_out.push((MooInst::Plus, (pos, 1)));
-                }
-                '-' => {
-                    program_out.push((MooInst::Minus, (pos, 1)));
-                }
-                '>' => {
-                    program_out.push((MooInst::Right, (pos, 1)));
-                }
-                '<' => {
-                    program_out.push((MooInst::Left, (pos, 1)));
-                }
-                '.' => {
-                    program_out.push((MooInst::Out, (pos, 1)));
-                }
-                ',' => {
-                    program_out.push((MooInst::In, (pos, 1)));
-                }
-                '[' => {
-                    program_out.push((MooInst::OpenLoop, (pos, 1)));
-                }
-                ']' => {
-                    program_out.push((MooInst::CloseLoop, (pos, 1)));
-                }
-                '(' => {
-                    // If we are in a definition we can't re-define
-                    if current_function_definition.is_some() {
-                        return Err(Box::new(MooError::new(
-                            "Re-opening definition in one another",
-                        )));
-                    }
-                    // Fetch the name of the definition
-                    let (ident, eaten) = fetch_identifier(&mut method_fetcher)?;
-                    //println!("Found ident {} (peek {:?})", ident, method_fetcher.peek());
-                    // Check the closing paren, colon and opening bracket
-                    for guard in [')', ':', '{'] {
-                        if method_fetcher.next() != Some(guard) {
-                            return Err(Box::new(MooError::new(&format!(
-                                "Expected '{guard}' after function declaration header"
-                            ))));
-                        }
-                    }
+    /// Getters and setters
+    #[must_use]
+    pub fn get_input(&self) -> &[u8] {
+        &self.input
+    }
 
-                    // Do we know about the function ?
-                    let function_code = if let Some(&c) = method_lookup.get(&ident) {
-                        c
-                    } else {
-                        method_lookup.insert(ident, method_lookup.len());
-                        method_lookup.len() - 1
-                    };
-                    // Insert a code
-                    method_index.insert(function_code, program_out.len());
-                    program_out.push((MooInst::FuncStart(function_code), (pos, 4 + eaten)));
-                    current_function_definition = Some(function_code);
-                    pos += 3 + eaten;
-                }
-                '}' => {
-                    if let Some(current) = current_function_definition {
-                        program_out.push((MooInst::FuncEnd(current), (pos, 1)));
-                        current_function_definition = None;
-                    } else {
-                        return Err(Box::new(MooError::new(
-                            "Ended an unknown function definition",
-                        )));
-                    }
-                }
-                '~' => {
-                    // Fetch the name of the function
-                    let (ident, eaten) = fetch_identifier(&mut method_fetcher)?;
-                    // Check the semicolon
-                    if method_fetcher.next() != Some(';') {
-                        return Err(Box::new(MooError::new(
-                            "Expected ';' after function call identifier",
-                        )));
-                    }
-                    // Get the identifier
-                    let method_code = if let Some(&c) = method_lookup.get(&ident) {
-                        c
-                    } else {
-                        let n = method_lookup.len();
-                        method_lookup.insert(ident, n);
-                        n
-                    };
-                    // Insert the call
-                    program_out.push((MooInst::Call(method_code), (pos, eaten + 2)));
-                    pos += 1 + eaten;
-                }
-                '^' => {
-                    program_out.push((MooInst::MetaJump, (pos, 1)));
-                }
-                c => {
-                    program_out.push((MooInst::Nop(c), (pos, 1)));
-                }
-            }
-            pos += 1;
+    /// Obtain how many bytes of the input were consumed
+    #[must_use]
+    pub fn get_input_position(&self) -> usize {
+        self.input_position
+    }
+
+    /// Add bytes at the end of the input
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+        self.waiting = false;
+    }
+
+    /// Insert bytes in the input at the given position
+    ///
+    /// Consumed bytes cannot be changed anymore, so positions before the
+    /// first pending byte insert right before it.
+    pub fn insert_input(&mut self, at: usize, bytes: &[u8]) {
+        let at = at.clamp(self.input_position, self.input.len());
+        self.input.splice(at..at, bytes.iter().copied());
+        self.waiting = false;
+    }
+
+    /// Remove bytes from the input, leaving out those consumed already
+    pub fn remove_input(&mut self, range: Range<usize>) {
+        let start = range.start.max(self.input_position);
+        let end = range.end.min(self.input.len());
+        if start >= end {
+            return;
         }
+        self.input.drain(start..end);
+    }
 
-        program_out.push((MooInst::Halt, (pos, 1)));
-        Ok((program_out, method_index))
+    #[must_use]
+    pub fn get_eof_policy(&self) -> EofPolicy {
+        self.eof_policy
     }
 
-    /// Getters and setters
-    pub fn get_input(&self) -> &str {
-        &self.input
+    pub fn set_eof_policy(&mut self, policy: EofPolicy) {
+        self.eof_policy = policy;
+    }
+
+    /// Whether the program is stuck on a `,` until more input is provided
+    #[must_use]
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
     }
 
-    pub fn get_output(&self) -> &str {
+    #[must_use]
+    pub fn get_output(&self) -> &[u8] {
         &self.output
     }
 
-    pub fn get_output_length(&self) -> usize {
-        UnicodeSegmentation::graphemes(self.output.as_str(), true).count()
+    /// Take the output produced since it was last taken, leaving it empty
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
     }
 
-    pub fn get_data_pointer(&self) -> usize {
+    #[must_use]
+    pub fn get_data_pointer(&self) -> isize {
         self.pointer
     }
 
-    pub fn get_ribbon_around(&self, count: usize) -> Vec<u8> {
-        (self.pointer.div_euclid(count) * count..(self.pointer.div_euclid(count) + 1) * count)
-            .map(|x| self.data_ribbon.get(&x).unwrap_or(&0))
-            .copied()
-            .collect::<Vec<u8>>()
+    #[must_use]
+    pub fn get_tape_mode(&self) -> TapeMode {
+        self.tape_mode
+    }
+
+    /// Change the shape of the ribbons, and reset the program
+    pub fn set_tape_mode(&mut self, mode: TapeMode) {
+        self.tape_mode = mode;
+        self.reset();
+    }
+
+    #[must_use]
+    pub fn get_overflow_mode(&self) -> OverflowMode {
+        self.overflow_mode
+    }
+
+    pub fn set_overflow_mode(&mut self, mode: OverflowMode) {
+        self.overflow_mode = mode;
+    }
+
+    /// Obtain the addresses and values of the `count` cells of the data
+    /// ribbon around the pointer
+    ///
+    /// The ribbon is split in pages of `count` cells so that the cells shown
+    /// do not move along with the pointer. On a ring, the last page wraps
+    /// around to the first cells, and rings smaller than a page are shown
+    /// whole.
+    #[must_use]
+    pub fn get_ribbon_around(&self, count: usize) -> Vec<(isize, C)> {
+        let count = isize::try_from(count.max(1)).unwrap_or(isize::MAX);
+        let page = self.pointer.div_euclid(count) * count;
+        let addresses: Vec<isize> = match self.tape_mode {
+            TapeMode::Circular(cells) => {
+                let cells = isize::try_from(cells.get()).unwrap_or(isize::MAX);
+                if cells <= count {
+                    (0..cells).collect()
+                } else {
+                    (page..page + count).map(|x| x.rem_euclid(cells)).collect()
+                }
+            }
+            _ => (page..page + count).collect(),
+        };
+        addresses
+            .into_iter()
+            .map(|x| (x, self.data_ribbon.get(&x).copied().unwrap_or_default()))
+            .collect()
+    }
+
+    /// Obtain the address of the first cell written to on `ribbon`, and the
+    /// values of every cell from there to the last one written to
+    ///
+    /// Returns `None` if nothing was written to the ribbon yet.
+    #[must_use]
+    pub fn get_touched_cells(&self, ribbon: Ribbon) -> Option<(isize, Vec<C>)> {
+        let cells = match ribbon {
+            Ribbon::Data => &self.data_ribbon,
+            Ribbon::Meta => &self.meta_ribbon,
+        };
+        let start = *cells.keys().min()?;
+        let end = *cells.keys().max()?;
+        let values = (start..=end)
+            .map(|x| cells.get(&x).copied().unwrap_or_default())
+            .collect();
+        Some((start, values))
+    }
+
+    /// Obtain the loops and calls being run, innermost first, up to `max_of`
+    /// of them
+    #[must_use]
+    pub fn get_frames(&self, max_of: Option<usize>) -> Vec<Frame<'_>> {
+        let mut loops = self.loop_stack.iter().rev().peekable();
+        let mut calls = self.call_stack.iter().enumerate().rev().peekable();
+        let mut frames: Vec<Frame> = Vec::new();
+        while frames.len() < max_of.unwrap_or(usize::MAX) {
+            // Loops entered during a call come before it
+            let take_loop = match (loops.peek(), calls.peek()) {
+                (Some(frame), Some((depth, _))) => frame.calls > *depth,
+                (frame, _) => frame.is_some(),
+            };
+            let frame = if take_loop {
+                loops.next().map(|frame| Frame::Loop {
+                    start: self.program[frame.start].1,
+                })
+            } else {
+                calls.next().map(|(_, frame)| Frame::Call {
+                    function: &self.method_names[frame.function],
+                    call: self.program[frame.call].1,
+                    collapsed: frame.collapsed,
+                })
+            };
+            match frame {
+                Some(frame) => frames.push(frame),
+                None => break,
+            }
+        }
+        frames
+    }
+
+    /// Obtain how many loops and calls are being run
+    #[must_use]
+    pub fn get_stack_depths(&self) -> (usize, usize) {
+        (self.loop_stack.len(), self.call_stack.len())
+    }
+
+    /// Obtain the deepest the loop and call stacks went since the last reset
+    #[must_use]
+    pub fn get_stack_high_water(&self) -> (usize, usize) {
+        (self.loop_high_water, self.call_high_water)
+    }
+
+    #[must_use]
+    pub fn get_max_call_depth(&self) -> Option<usize> {
+        self.limits.depth
+    }
+
+    /// Limit how deep calls can go, or let them go as deep as memory allows
+    pub fn set_max_call_depth(&mut self, depth: Option<usize>) {
+        self.limits.depth = depth;
+    }
+
+    #[must_use]
+    pub fn get_limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Change the resources the program may use, which applies to what it
+    /// used since the last reset
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
     }
 
-    pub fn jump_list(&self, max_of: Option<usize>) -> Vec<usize> {
-        self.return_positions
+    /// Obtain how many steps ran since the last reset
+    #[must_use]
+    pub fn get_step_count(&self) -> u64 {
+        self.steps
+    }
+
+    /// Obtain how long the program may still run for, if its time is limited
+    #[must_use]
+    pub fn get_time_left(&self) -> Option<Duration> {
+        let elapsed = self
+            .started
+            .map_or(Duration::ZERO, |started| started.elapsed());
+        self.limits.time.map(|max| max.saturating_sub(elapsed))
+    }
+
+    /// Obtain the spans of all comments in the program
+    #[must_use]
+    pub fn get_comment_spans(&self) -> Vec<Span> {
+        self.program
             .iter()
-            .take(max_of.unwrap_or(self.return_positions.len()))
-            .copied()
-            .collect::<Vec<usize>>()
+            .filter(|(inst, _)| matches!(inst, MooInst::Comment))
+            .map(|(_, span)| *span)
+            .collect()
     }
 
     /// Obtain the span of the next instruction to be executed
-    pub fn get_instruction_span(&self) -> (usize, usize) {
+    #[must_use]
+    pub fn get_instruction_span(&self) -> Span {
         self.next_instruction().1
     }
 
-    fn next_instruction(&self) -> &(MooInst, (usize, usize)) {
-        self.program.get(self.instruction_pointer).unwrap()
+    /// Obtain the files the program was loaded from, main file first
+    #[must_use]
+    pub fn get_sources(&self) -> &[SourceFile] {
+        &self.sources
     }
 
-    fn save_pointer(&mut self) {
-        self.return_positions.push_front(self.instruction_pointer);
+    fn next_instruction(&self) -> &SpannedInstruction {
+        self.program.get(self.instruction_pointer).unwrap()
     }
 
-    fn retrieve_pointer(&mut self) -> usize {
-        self.return_positions.pop_front().unwrap()
+    /// Enter the loop at the current position
+    fn enter_loop(&mut self) {
+        self.loop_stack.push(LoopFrame {
+            start: self.instruction_pointer,
+            calls: self.call_stack.len(),
+        });
+        self.loop_high_water = self.loop_high_water.max(self.loop_stack.len());
     }
 
-    /// Get the underlying [`u8`] value from the ribbon
-    pub fn get_value(&self) -> u8 {
-        if self.is_meta {
-            *self.meta_ribbon.get(&self.meta_pointer).unwrap_or(&0)
+    /// Call the function `function` from the current position
+    fn call(&mut self, function: usize) -> Result<(), RuntimeErrorKind> {
+        // Find the function position
+        let position = *self.method_index.get(&function).ok_or_else(|| {
+            RuntimeErrorKind::UndefinedFunction(self.method_names[function].clone())
+        })?;
+        if self.is_tail_call() {
+            // Nothing is left to run in the caller, so the callee can return
+            // right where it would have
+            if let Some(frame) = self.call_stack.last_mut() {
+                frame.function = function;
+                frame.collapsed += 1;
+            }
         } else {
-            *self.data_ribbon.get(&self.pointer).unwrap_or(&0)
+            if let Some(max) = self.limits.depth {
+                if self.call_stack.len() >= max {
+                    return Err(RuntimeErrorKind::StackOverflow(max));
+                }
+            }
+            // Save the current position to jump back
+            self.call_stack.push(CallFrame {
+                function,
+                call: self.instruction_pointer,
+                collapsed: 0,
+            });
+            self.call_high_water = self.call_high_water.max(self.call_stack.len());
         }
+        // Jump right into the body
+        self.instruction_pointer = position + 1;
+        Ok(())
+    }
+
+    /// Get the underlying value from the ribbon
+    #[must_use]
+    pub fn get_value(&self) -> C {
+        let value = if self.is_meta {
+            self.meta_ribbon.get(&self.meta_pointer)
+        } else {
+            self.data_ribbon.get(&self.pointer)
+        };
+        value.copied().unwrap_or_default()
     }
 
     /// Set value at the current pointer location (data or meta)
-    pub fn set_value(&mut self, v: u8) {
+    pub fn set_value(&mut self, v: C) {
         if self.is_meta {
             self.meta_ribbon.insert(self.meta_pointer, v);
         } else {
@@ -365,161 +1465,532 @@ impl Runner {
         }
     }
 
-    pub fn plus(&mut self) {
-        if self.is_meta {
-            let res = self
-                .meta_ribbon
-                .entry(self.meta_pointer)
-                .or_insert(0)
-                .wrapping_add(1);
-            self.meta_ribbon.insert(self.meta_pointer, res);
+    /// Add one to the value at the current pointer location
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is the largest one and overflows trap,
+    /// or if the cell is a new one and the ribbon holds as many as allowed.
+    pub fn plus(&mut self) -> Result<(), RuntimeErrorKind> {
+        self.check_cell_limit()?;
+        let value = self.get_value();
+        let result = match (value.checked_increment(), self.overflow_mode) {
+            (Some(result), _) => result,
+            (None, OverflowMode::Wrapping) => value.wrapping_increment(),
+            (None, OverflowMode::Saturating) => value,
+            (None, OverflowMode::Trapping) => {
+                return Err(RuntimeErrorKind::CellOverflow(self.current_ribbon()));
+            }
+        };
+        self.set_value(result);
+        Ok(())
+    }
+
+    /// Remove one from the value at the current pointer location
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is the smallest one and overflows trap,
+    /// or if the cell is a new one and the ribbon holds as many as allowed.
+    pub fn minus(&mut self) -> Result<(), RuntimeErrorKind> {
+        self.check_cell_limit()?;
+        let value = self.get_value();
+        let result = match (value.checked_decrement(), self.overflow_mode) {
+            (Some(result), _) => result,
+            (None, OverflowMode::Wrapping) => value.wrapping_decrement(),
+            (None, OverflowMode::Saturating) => value,
+            (None, OverflowMode::Trapping) => {
+                return Err(RuntimeErrorKind::CellUnderflow(self.current_ribbon()));
+            }
+        };
+        self.set_value(result);
+        Ok(())
+    }
+
+    /// Fail if the current cell would be a new one on a ribbon holding as
+    /// many as allowed
+    fn check_cell_limit(&self) -> Result<(), RuntimeErrorKind> {
+        let Some(max) = self.limits.cells else {
+            return Ok(());
+        };
+        let (cells, pointer) = if self.is_meta {
+            (&self.meta_ribbon, self.meta_pointer)
         } else {
-            let res = self
-                .data_ribbon
-                .entry(self.pointer)
-                .or_insert(0)
-                .wrapping_add(1);
-            self.data_ribbon.insert(self.pointer, res);
+            (&self.data_ribbon, self.pointer)
+        };
+        if cells.len() >= max && !cells.contains_key(&pointer) {
+            return Err(RuntimeErrorKind::CellLimit(self.current_ribbon(), max));
+        }
+        Ok(())
+    }
+
+    /// Fail if the program used up its steps or its time
+    fn check_run_limits(&mut self) -> Result<(), RuntimeErrorKind> {
+        if let Some(max) = self.limits.steps {
+            if self.steps >= max {
+                return Err(RuntimeErrorKind::StepLimit(max));
+            }
+        }
+        if let Some(max) = self.limits.time {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            // Looking at the clock is slow next to running a step, but not
+            // next to waiting for input
+            let look = self.waiting || self.steps.is_multiple_of(STEPS_PER_CLOCK_CHECK);
+            if look && started.elapsed() >= max {
+                return Err(RuntimeErrorKind::TimeLimit(max));
+            }
         }
+        Ok(())
     }
 
-    pub fn minus(&mut self) {
+    fn current_ribbon(&self) -> Ribbon {
         if self.is_meta {
-            let res = self
-                .meta_ribbon
-                .entry(self.meta_pointer)
-                .or_insert(0)
-                .wrapping_sub(1);
-            self.meta_ribbon.insert(self.meta_pointer, res);
+            Ribbon::Meta
         } else {
-            let res = self
-                .data_ribbon
-                .entry(self.pointer)
-                .or_insert(0)
-                .wrapping_sub(1);
-            self.data_ribbon.insert(self.pointer, res);
+            Ribbon::Data
         }
     }
 
-    pub fn step(&mut self) {
+    /// Execute instructions until one that is not a no-op has run
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, without running anything, if the next instruction
+    /// cannot be run. Stepping again fails the same way.
+    pub fn step(&mut self) -> Result<StepOutcome, RuntimeError> {
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
+        // Halting is free, so a program may use up its limits on the way
+        let (instr, span) = *self.next_instruction();
+        if !matches!(instr, MooInst::Halt) {
+            self.check_run_limits()
+                .map_err(|kind| RuntimeError { kind, span })?;
+        }
         loop {
             // Look at where we are
-            let (instr, _) = self.next_instruction();
+            let (instr, span) = *self.next_instruction();
+            let error = |kind: RuntimeErrorKind| RuntimeError { kind, span };
             match instr {
                 MooInst::Halt => {
                     self.halted = true;
+                    return Ok(StepOutcome::Halted);
                 }
-                MooInst::Plus => {
-                    self.plus();
-                    self.instruction_pointer += 1;
-                }
-                MooInst::Minus => {
-                    self.minus();
-                    self.instruction_pointer += 1;
-                }
-                MooInst::Left => {
-                    if self.is_meta {
-                        self.meta_pointer -= 1;
-                    } else {
-                        self.pointer -= 1;
-                    }
-                    self.instruction_pointer += 1;
-                }
-                MooInst::Right => {
-                    if self.is_meta {
-                        self.meta_pointer += 1;
-                    } else {
-                        self.pointer += 1;
-                    }
-                    self.instruction_pointer += 1;
-                }
+                MooInst::Plus => self.plus().map_err(error)?,
+                MooInst::Minus => self.minus().map_err(error)?,
+                MooInst::Left => self.move_pointer(-1).map_err(error)?,
+                MooInst::Right => self.move_pointer(1).map_err(error)?,
                 MooInst::OpenLoop => {
-                    // Evaluate the current value
-                    let value = self.get_value();
-                    if value == 0 {
+                    if self.get_value() == C::default() {
                         // Find the next close bracket
                         let mut varen: usize = 1;
                         while varen > 0 {
                             self.instruction_pointer += 1;
                             match self.next_instruction().0 {
-                                MooInst::OpenLoop => {
-                                    varen += 1;
-                                }
-                                MooInst::CloseLoop => {
-                                    varen -= 1;
-                                }
+                                MooInst::OpenLoop => varen += 1,
+                                MooInst::CloseLoop => varen -= 1,
                                 _ => {}
                             }
                         }
-                        self.instruction_pointer += 1;
                     } else {
-                        // Push the value to memory
-                        self.save_pointer();
-                        // Move once
-                        self.instruction_pointer += 1;
+                        self.enter_loop();
                     }
                 }
                 MooInst::CloseLoop => {
                     // Move back to the opening of the loop
-                    self.instruction_pointer = self.retrieve_pointer();
+                    let frame = self
+                        .loop_stack
+                        .pop()
+                        .ok_or_else(|| error(RuntimeErrorKind::UnmatchedLoopEnd))?;
+                    self.instruction_pointer = frame.start;
+                    break;
                 }
                 MooInst::Out => {
                     // Get the current value under the cursor
-                    let val = self.get_value();
-                    let chr = char::from(val);
-                    self.output.push(chr);
-                    self.instruction_pointer += 1;
+                    self.output.push(self.get_value().to_byte());
                 }
                 MooInst::In => {
-                    /*
-                    match self.input.as_bytes().first() {
-                        Some(c) => {
-                            // Get the char value
-                            self.set_value(*c);
-                        }
-                        None => {
-                            // Find a way to paralyze running
-                            self.halted = true;
-                        }
+                    self.read_input().map_err(error)?;
+                    if self.waiting {
+                        // Try again on the next step
+                        return Ok(StepOutcome::Waiting);
                     }
-                    */
                 }
                 MooInst::Call(n) => {
-                    // Find the function position
-                    let position = *self.method_index.get(n).unwrap();
-                    // Save the current position + 1 to jump back
-                    self.save_pointer();
-                    // Jump
-                    self.instruction_pointer = position;
+                    self.call(n).map_err(error)?;
+                    break;
                 }
-                MooInst::FuncStart(_n) => {
-                    self.instruction_pointer += 1;
+                MooInst::FuncStart => {
+                    // Definitions are only ever run when called
+                    self.skip_definition();
+                    continue;
                 }
-                MooInst::FuncEnd(_) => {
-                    // Pop the pointer back
-                    let position = self.retrieve_pointer();
-                    self.instruction_pointer = position + 1;
+                MooInst::FuncEnd => {
+                    // Pop the pointer back, returning from the entry point
+                    // ends the program
+                    self.instruction_pointer = match self.call_stack.pop() {
+                        Some(frame) => frame.call + 1,
+                        None => self.program.len() - 1,
+                    };
+                    break;
                 }
-                MooInst::Nop(_c) => {
+                MooInst::Nop | MooInst::Comment | MooInst::Directive => {
                     // Move one and continue
                     self.instruction_pointer += 1;
                     continue;
                 }
-                MooInst::MetaJump => {
-                    self.is_meta = !self.is_meta;
+                MooInst::MetaJump => self.is_meta = !self.is_meta,
+            }
+            self.instruction_pointer += 1;
+            break;
+        }
+
+        self.skip_inert();
+        self.steps += 1;
+        Ok(StepOutcome::Ran)
+    }
+
+    /// Move the pointer of the current ribbon by one cell, to the left if
+    /// `offset` is -1 or to the right if it is 1
+    fn move_pointer(&mut self, offset: isize) -> Result<(), RuntimeErrorKind> {
+        let ribbon = self.current_ribbon();
+        let pointer = if self.is_meta {
+            &mut self.meta_pointer
+        } else {
+            &mut self.pointer
+        };
+        *pointer = match self.tape_mode {
+            TapeMode::Bounded if *pointer + offset < 0 => {
+                return Err(RuntimeErrorKind::TapeUnderflow(ribbon));
+            }
+            TapeMode::Bounded | TapeMode::Infinite => *pointer + offset,
+            TapeMode::Circular(cells) => {
+                (*pointer + offset).rem_euclid(isize::try_from(cells.get()).unwrap_or(isize::MAX))
+            }
+        };
+        Ok(())
+    }
+
+    /// Store the next byte of input in the current cell, or apply the EOF
+    /// policy if there is none
+    fn read_input(&mut self) -> Result<(), RuntimeErrorKind> {
+        self.waiting = false;
+        let byte = self.input.get(self.input_position).copied();
+        let value = if let Some(byte) = byte {
+            C::from_byte(byte)
+        } else {
+            match self.eof_policy {
+                EofPolicy::Zero => C::default(),
+                EofPolicy::Max => C::MAX,
+                EofPolicy::Unchanged => return Ok(()),
+                EofPolicy::Wait => {
+                    self.waiting = true;
+                    return Ok(());
+                }
+            }
+        };
+        // Nothing is consumed if the value cannot be stored
+        self.check_cell_limit()?;
+        if byte.is_some() {
+            self.input_position += 1;
+        }
+        self.set_value(value);
+        Ok(())
+    }
+
+    /// Whether the call at the current position is the last thing its
+    /// function does
+    fn is_tail_call(&self) -> bool {
+        self.program[self.instruction_pointer + 1..]
+            .iter()
+            .find(|(inst, _)| !matches!(inst, MooInst::Nop | MooInst::Comment | MooInst::Directive))
+            .is_some_and(|(inst, _)| matches!(inst, MooInst::FuncEnd))
+    }
+
+    /// Move forward as long as there is nothing to run, stepping over
+    /// function definitions met along the way
+    fn skip_inert(&mut self) {
+        loop {
+            match self.next_instruction().0 {
+                MooInst::Nop | MooInst::Comment | MooInst::Directive => {
                     self.instruction_pointer += 1;
                 }
+                MooInst::FuncStart => self.skip_definition(),
+                _ => break,
             }
-            break;
         }
+    }
 
-        // Move forward as long as it's a Nop
-        while let MooInst::Nop(_) = self.next_instruction().0 {
+    /// Move past the end of the function defined at the current position
+    ///
+    /// Helpers are laid out after their parent, so the first end met is the
+    /// right one.
+    fn skip_definition(&mut self) {
+        while !matches!(self.next_instruction().0, MooInst::FuncEnd) {
             self.instruction_pointer += 1;
         }
+        self.instruction_pointer += 1;
     }
 
+    #[must_use]
     pub fn is_halted(&self) -> bool {
         self.halted
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runner(code: &str) -> Runner {
+        Runner::new(code, Path::new("test.moo")).unwrap()
+    }
+
+    /// Runner of `count` times `command`, with cells of type `C`
+    fn repeated<C: Cell>(command: &str, count: usize, mode: OverflowMode) -> Runner<C> {
+        let mut runner = Runner::new(&command.repeat(count), Path::new("test.moo")).unwrap();
+        runner.set_overflow_mode(mode);
+        runner
+    }
+
+    /// Kinds of the errors met loading `code`
+    fn load_errors(code: &str) -> Vec<MooErrorKind> {
+        let Err(errors) = Runner::<u8>::new(code, Path::new("test.moo")) else {
+            panic!("loaded: {code}");
+        };
+        errors
+            .errors
+            .iter()
+            .map(|error| error.kind().clone())
+            .collect()
+    }
+
+    /// Step until the program stops, and tell how
+    fn run<C: Cell>(runner: &mut Runner<C>) -> Result<StepOutcome, RuntimeErrorKind> {
+        loop {
+            match runner.step() {
+                Ok(StepOutcome::Ran) => {}
+                Ok(outcome) => return Ok(outcome),
+                Err(error) => return Err(error.kind().clone()),
+            }
+        }
+    }
+
+    #[test]
+    fn step_limit_allows_exactly_that_many_steps() {
+        let mut limited = runner("+++.");
+        limited.set_limits(Limits {
+            steps: Some(4),
+            ..Limits::default()
+        });
+        assert_eq!(run(&mut limited), Ok(StepOutcome::Halted));
+        assert_eq!(limited.get_step_count(), 4);
+        assert_eq!(limited.get_output(), [3]);
+    }
+
+    #[test]
+    fn step_limit_stops_the_step_past_it() {
+        let mut limited = runner("+++.");
+        limited.set_limits(Limits {
+            steps: Some(3),
+            ..Limits::default()
+        });
+        assert_eq!(run(&mut limited), Err(RuntimeErrorKind::StepLimit(3)));
+        assert_eq!(limited.get_step_count(), 3);
+        assert!(limited.get_output().is_empty());
+    }
+
+    #[test]
+    fn remove_input_keeps_the_consumed_bytes() {
+        let mut reading = runner(",,");
+        reading.push_input(b"abcd");
+        assert_eq!(run(&mut reading), Ok(StepOutcome::Halted));
+        reading.remove_input(0..1);
+        assert_eq!(reading.get_input(), b"abcd");
+        reading.remove_input(1..3);
+        assert_eq!(reading.get_input(), b"abd");
+        reading.remove_input(3..10);
+        assert_eq!(reading.get_input(), b"abd");
+        reading.remove_input(2..10);
+        assert_eq!(reading.get_input(), b"ab");
+    }
+
+    #[test]
+    fn tail_calls_reuse_the_frame() {
+        let mut tail = runner("(g):{^}(f):{+~g;}~f;");
+        while !matches!(
+            tail.get_frames(None)[..],
+            [Frame::Call { function: "g", .. }]
+        ) {
+            assert_eq!(tail.step(), Ok(StepOutcome::Ran));
+        }
+        assert_eq!(tail.get_stack_depths().1, 1);
+        assert!(matches!(
+            tail.get_frames(None)[..],
+            [Frame::Call {
+                function: "g",
+                collapsed: 1,
+                ..
+            }]
+        ));
+        assert_eq!(run(&mut tail), Ok(StepOutcome::Halted));
+        assert_eq!(tail.get_stack_high_water().1, 1);
+    }
+
+    #[test]
+    fn calls_followed_by_more_code_grow_the_stack() {
+        let mut recursive = runner("(c):{-[~c;]}~c;");
+        recursive.set_max_call_depth(Some(8));
+        assert_eq!(run(&mut recursive), Err(RuntimeErrorKind::StackOverflow(8)));
+        assert_eq!(recursive.get_stack_depths().1, 8);
+    }
+
+    #[test]
+    fn tail_call_from_main_halts() {
+        let mut entry = runner("(g):{+.}(main):{~g;}");
+        assert_eq!(run(&mut entry), Ok(StepOutcome::Halted));
+        assert_eq!(entry.get_output(), [1]);
+        assert_eq!(entry.get_stack_high_water().1, 0);
+    }
+
+    #[test]
+    fn helpers_are_not_visible_outside_of_their_parent() {
+        assert_eq!(
+            load_errors("(p):{(h):{+}~h;}~h;"),
+            [MooErrorKind::UndefinedFunction("h".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn helpers_hide_globals_of_the_same_name() {
+        let mut shadowed = runner("(h):{++}(p):{(h):{+}~h;}~p;~h;.");
+        assert_eq!(run(&mut shadowed), Ok(StepOutcome::Halted));
+        assert_eq!(shadowed.get_output(), [3]);
+    }
+
+    #[test]
+    fn sibling_helpers_call_each_other() {
+        let mut siblings = runner("(p):{(a):{+~b;}(b):{++}~a;}~p;.");
+        assert_eq!(run(&mut siblings), Ok(StepOutcome::Halted));
+        assert_eq!(siblings.get_output(), [3]);
+    }
+
+    #[test]
+    fn definitions_are_skipped_past_their_helpers() {
+        let mut nested = runner("(p):{(h):{(i):{+}~i;~i;}~h;+}.~p;.");
+        assert_eq!(run(&mut nested), Ok(StepOutcome::Halted));
+        assert_eq!(nested.get_output(), [0, 3]);
+    }
+
+    #[test]
+    fn helpers_are_only_defined_again_as_overrides() {
+        assert_eq!(
+            load_errors("(p):{(h):{+}(h):{-}}"),
+            [MooErrorKind::DuplicateDefinition("h".to_string())]
+        );
+        assert!(Runner::<u8>::new("(p):{(h):{+}(!h):{-}}", Path::new("test.moo")).is_ok());
+    }
+
+    #[test]
+    fn bounded_tape_stops_left_of_the_first_cell() {
+        let mut bounded = runner("+<");
+        assert_eq!(
+            run(&mut bounded),
+            Err(RuntimeErrorKind::TapeUnderflow(Ribbon::Data))
+        );
+        assert_eq!(bounded.get_instruction_span(), Span::new(0, 1, 1));
+    }
+
+    #[test]
+    fn infinite_tape_goes_on_left_of_zero() {
+        let mut infinite = runner("<<<+");
+        infinite.set_tape_mode(TapeMode::Infinite);
+        assert_eq!(run(&mut infinite), Ok(StepOutcome::Halted));
+        assert_eq!(infinite.get_data_pointer(), -3);
+        assert_eq!(
+            infinite.get_ribbon_around(4),
+            [(-4, 0), (-3, 1), (-2, 0), (-1, 0)]
+        );
+    }
+
+    #[test]
+    fn circular_tape_smaller_than_a_page_is_shown_whole() {
+        let mut ring = runner("<+");
+        ring.set_tape_mode(TapeMode::Circular(NonZeroUsize::new(3).unwrap()));
+        assert_eq!(run(&mut ring), Ok(StepOutcome::Halted));
+        assert_eq!(ring.get_data_pointer(), 2);
+        assert_eq!(ring.get_ribbon_around(8), [(0, 0), (1, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn circular_tape_pages_wrap_around_its_end() {
+        let mut ring = runner("<+");
+        ring.set_tape_mode(TapeMode::Circular(NonZeroUsize::new(10).unwrap()));
+        assert_eq!(run(&mut ring), Ok(StepOutcome::Halted));
+        assert_eq!(ring.get_data_pointer(), 9);
+        assert_eq!(ring.get_ribbon_around(4), [(8, 0), (9, 1), (0, 0), (1, 0)]);
+        let mut ring = runner("<+>++");
+        ring.set_tape_mode(TapeMode::Circular(NonZeroUsize::new(10).unwrap()));
+        assert_eq!(run(&mut ring), Ok(StepOutcome::Halted));
+        assert_eq!(ring.get_data_pointer(), 0);
+        assert_eq!(ring.get_ribbon_around(4), [(0, 2), (1, 0), (2, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn wrapping_goes_back_to_the_other_end() {
+        let mut top = repeated::<i8>("+", 128, OverflowMode::Wrapping);
+        assert_eq!(run(&mut top), Ok(StepOutcome::Halted));
+        assert_eq!(top.get_value(), i8::MIN);
+        let mut bottom = repeated::<u8>("-", 1, OverflowMode::Wrapping);
+        assert_eq!(run(&mut bottom), Ok(StepOutcome::Halted));
+        assert_eq!(bottom.get_value(), u8::MAX);
+    }
+
+    #[test]
+    fn saturating_stays_on_the_bounds() {
+        let mut top = repeated::<i8>("+", 130, OverflowMode::Saturating);
+        assert_eq!(run(&mut top), Ok(StepOutcome::Halted));
+        assert_eq!(top.get_value(), i8::MAX);
+        let mut bottom = repeated::<i8>("-", 130, OverflowMode::Saturating);
+        assert_eq!(run(&mut bottom), Ok(StepOutcome::Halted));
+        assert_eq!(bottom.get_value(), i8::MIN);
+        let mut top = repeated::<u8>("+", 300, OverflowMode::Saturating);
+        assert_eq!(run(&mut top), Ok(StepOutcome::Halted));
+        assert_eq!(top.get_value(), u8::MAX);
+        let mut bottom = repeated::<u8>("-", 2, OverflowMode::Saturating);
+        assert_eq!(run(&mut bottom), Ok(StepOutcome::Halted));
+        assert_eq!(bottom.get_value(), u8::MIN);
+    }
+
+    #[test]
+    fn trapping_stops_on_the_command_going_past_the_bounds() {
+        let mut top = repeated::<i8>("+", 128, OverflowMode::Trapping);
+        assert_eq!(
+            run(&mut top),
+            Err(RuntimeErrorKind::CellOverflow(Ribbon::Data))
+        );
+        assert_eq!(top.get_instruction_span(), Span::new(0, 127, 1));
+        assert_eq!(top.get_value(), i8::MAX);
+        let mut bottom = repeated::<i8>("-", 129, OverflowMode::Trapping);
+        assert_eq!(
+            run(&mut bottom),
+            Err(RuntimeErrorKind::CellUnderflow(Ribbon::Data))
+        );
+        assert_eq!(bottom.get_instruction_span(), Span::new(0, 128, 1));
+        assert_eq!(bottom.get_value(), i8::MIN);
+        let mut top = repeated::<u8>("+", 256, OverflowMode::Trapping);
+        assert_eq!(
+            run(&mut top),
+            Err(RuntimeErrorKind::CellOverflow(Ribbon::Data))
+        );
+        assert_eq!(top.get_instruction_span(), Span::new(0, 255, 1));
+        assert_eq!(top.get_value(), u8::MAX);
+        let mut bottom = repeated::<u8>("-", 1, OverflowMode::Trapping);
+        assert_eq!(
+            run(&mut bottom),
+            Err(RuntimeErrorKind::CellUnderflow(Ribbon::Data))
+        );
+        assert_eq!(bottom.get_instruction_span(), Span::new(0, 0, 1));
+        assert_eq!(bottom.get_value(), u8::MIN);
+    }
+}
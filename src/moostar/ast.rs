@@ -0,0 +1,575 @@
+//! Syntax tree of Moostar programs
+//!
+//! [`parse`] turns the text of a single file into a [`Program`] without
+//! resolving calls or includes, so that tools such as formatters or linters
+//! can work on the structure of the code rather than on its text.
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::str::Chars;
+
+use super::{MooError, MooErrorKind, Span};
+
+/// The contents of a Moostar file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// Top level nodes, in source order
+    pub nodes: Vec<Node>,
+}
+
+/// A single element of a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub kind: NodeKind,
+    /// Location of the node; for loops and functions, this is only their
+    /// opening `[` or header
+    pub span: Span,
+}
+
+/// The different elements a program is made of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeKind {
+    /// `+`
+    Plus,
+    /// `-`
+    Minus,
+    /// `<`
+    Left,
+    /// `>`
+    Right,
+    /// `,`
+    In,
+    /// `.`
+    Out,
+    /// `^`, switching between the data and meta ribbons
+    MetaToggle,
+    /// `[ ... ]`, with the location of the closing `]`
+    Loop { body: Vec<Node>, end: Span },
+    /// `(name):{ ... }` or `(!name):{ ... }` for overrides, with the location
+    /// of the closing `}`
+    Function {
+        name: String,
+        overrides: bool,
+        body: Vec<Node>,
+        end: Span,
+    },
+    /// `~name;`
+    Call(String),
+    /// `@include "path";`, any other `@` being text
+    Include(PathBuf),
+    /// `// ...`, without the slashes
+    LineComment(String),
+    /// `/* ... */`, without the delimiters
+    BlockComment(String),
+    /// Any other character
+    Text(char),
+}
+
+/// Parse the text of a file
+///
+/// `file` is the index stored in the spans of the resulting nodes and
+/// errors, which can be `0` when there is only one file involved.
+///
+/// # Errors
+///
+/// Returns the first syntax error met, such as an unbalanced bracket or a
+/// malformed function header.
+pub fn parse(text: &str, file: usize) -> Result<Program, MooError> {
+    Parser {
+        chars: text.chars().peekable(),
+        file,
+        pos: 0,
+    }
+    .parse()
+}
+
+/// A loop or function whose closing bracket has not been met yet
+struct Open {
+    span: Span,
+    nodes: Vec<Node>,
+    /// Name and override marker, for functions
+    function: Option<(String, bool)>,
+}
+
+struct Parser<'s> {
+    chars: Peekable<Chars<'s>>,
+    file: usize,
+    /// Position of the next character, in bytes
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        self.pos += c.map_or(0, char::len_utf8);
+        c
+    }
+
+    fn next_if(&mut self, predicate: impl FnOnce(&char) -> bool) -> Option<char> {
+        let c = self.chars.next_if(predicate);
+        self.pos += c.map_or(0, char::len_utf8);
+        c
+    }
+
+    /// Error pointing at the next character
+    fn error_here(&mut self, kind: MooErrorKind) -> MooError {
+        let len = self.chars.peek().map_or(1, |c| c.len_utf8());
+        self.error(kind, self.pos, len)
+    }
+
+    /// Span from `start` up to the next character
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(self.file, start, self.pos - start)
+    }
+
+    fn error(&self, kind: MooErrorKind, start: usize, len: usize) -> MooError {
+        MooError::new(kind, Span::new(self.file, start, len))
+    }
+
+    fn parse(mut self) -> Result<Program, MooError> {
+        let mut open: Vec<Open> = Vec::new();
+        let mut nodes: Vec<Node> = Vec::new();
+        while let Some(c) = self.next() {
+            let start = self.pos - c.len_utf8();
+            let here = self.span_from(start);
+            let simple = |kind: NodeKind| Node { kind, span: here };
+            let node = match c {
+                '+' => simple(NodeKind::Plus),
+                '-' => simple(NodeKind::Minus),
+                '>' => simple(NodeKind::Right),
+                '<' => simple(NodeKind::Left),
+                '.' => simple(NodeKind::Out),
+                ',' => simple(NodeKind::In),
+                '^' => simple(NodeKind::MetaToggle),
+                '[' => {
+                    open.push(Open {
+                        span: here,
+                        nodes: Vec::new(),
+                        function: None,
+                    });
+                    continue;
+                }
+                '(' => {
+                    let function = self.header()?;
+                    open.push(Open {
+                        span: self.span_from(start),
+                        nodes: Vec::new(),
+                        function: Some(function),
+                    });
+                    continue;
+                }
+                ']' | '}' => Self::close(&mut open, c, here)?,
+                '~' => {
+                    let name = self.identifier()?;
+                    if self.next() != Some(';') {
+                        return Err(MooError::new(
+                            MooErrorKind::MissingSemicolon,
+                            self.span_from(start),
+                        ));
+                    }
+                    Node {
+                        kind: NodeKind::Call(name),
+                        span: self.span_from(start),
+                    }
+                }
+                // Any other `@` is text, as in an email address in prose
+                '@' if self.chars.clone().take(7).eq("include".chars()) => {
+                    let path = self.include(start)?;
+                    Node {
+                        kind: NodeKind::Include(path),
+                        span: self.span_from(start),
+                    }
+                }
+                '/' => match self.comment(start)? {
+                    Some(kind) => Node {
+                        kind,
+                        span: self.span_from(start),
+                    },
+                    None => simple(NodeKind::Text('/')),
+                },
+                c => simple(NodeKind::Text(c)),
+            };
+            match open.last_mut() {
+                Some(parent) => parent.nodes.push(node),
+                None => nodes.push(node),
+            }
+        }
+        // A definition left open is more telling than the loops inside of it
+        if let Some(function) = open.iter().find(|o| o.function.is_some()) {
+            return Err(MooError::new(
+                MooErrorKind::UnterminatedDefinition,
+                function.span,
+            ));
+        }
+        match open.pop() {
+            Some(loop_start) => Err(MooError::new(
+                MooErrorKind::UnmatchedOpenLoop,
+                loop_start.span,
+            )),
+            None => Ok(Program { nodes }),
+        }
+    }
+
+    /// Close the innermost loop (for `]`) or function (for `}`), whose
+    /// closing bracket is at `here`
+    fn close(open: &mut Vec<Open>, bracket: char, here: Span) -> Result<Node, MooError> {
+        match (open.pop(), bracket) {
+            (
+                Some(Open {
+                    span,
+                    nodes: body,
+                    function: None,
+                }),
+                ']',
+            ) => Ok(Node {
+                kind: NodeKind::Loop { body, end: here },
+                span,
+            }),
+            (
+                Some(Open {
+                    span,
+                    nodes: body,
+                    function: Some((name, overrides)),
+                }),
+                '}',
+            ) => Ok(Node {
+                kind: NodeKind::Function {
+                    name,
+                    overrides,
+                    body,
+                    end: here,
+                },
+                span,
+            }),
+            // A bracket of the wrong kind only crosses something if there
+            // is something of its own kind further out to close
+            (Some(_), ']') if open.iter().any(|o| o.function.is_none()) => {
+                Err(MooError::new(MooErrorKind::LoopClosedAcrossFunction, here))
+            }
+            (Some(loop_start), '}') if open.iter().any(|o| o.function.is_some()) => Err(
+                MooError::new(MooErrorKind::LoopOpenAtFunctionEnd, loop_start.span),
+            ),
+            (_, ']') => Err(MooError::new(MooErrorKind::UnmatchedCloseLoop, here)),
+            (_, _) => Err(MooError::new(MooErrorKind::UnexpectedFunctionEnd, here)),
+        }
+    }
+
+    /// Fetch an identifier, along with the whitespace around it
+    fn identifier(&mut self) -> Result<String, MooError> {
+        while self.next_if(|c| c.is_whitespace()).is_some() {}
+        match self.chars.peek() {
+            // Is it a lower ascii ?
+            Some(c) if c.is_ascii_lowercase() => {}
+            Some(_) => return Err(self.error_here(MooErrorKind::InvalidIdentifierStart)),
+            None => return Err(self.error_here(MooErrorKind::EmptyIdentifier)),
+        }
+        let mut identifier: String = String::new();
+        while let Some(c) = self.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            identifier.push(c);
+        }
+        while self.next_if(|c| c.is_whitespace()).is_some() {}
+        Ok(identifier)
+    }
+
+    /// Fetch the header of a function definition, right after its `(`
+    ///
+    /// Returns the name of the function and whether the definition is marked
+    /// as an override with a leading `!`
+    fn header(&mut self) -> Result<(String, bool), MooError> {
+        let overrides = self.next_if(|c| *c == '!').is_some();
+        let name = self.identifier()?;
+        // Check the closing paren, colon and opening bracket
+        for guard in [')', ':', '{'] {
+            if self.chars.peek() != Some(&guard) {
+                return Err(self.error_here(MooErrorKind::MalformedHeader(guard)));
+            }
+            self.next();
+        }
+        Ok((name, overrides))
+    }
+
+    /// Fetch the rest of a comment, right after its first `/`
+    ///
+    /// Line comments run up to the end of the line, block comments up to
+    /// their closing `*/`. Returns nothing if the `/` does not start a
+    /// comment.
+    fn comment(&mut self, start: usize) -> Result<Option<NodeKind>, MooError> {
+        match self.chars.peek() {
+            Some('/') => {
+                self.next();
+                let mut text: String = String::new();
+                while let Some(c) = self.next_if(|c| *c != '\n') {
+                    text.push(c);
+                }
+                Ok(Some(NodeKind::LineComment(text)))
+            }
+            Some('*') => {
+                self.next();
+                let mut text: String = String::new();
+                while let Some(c) = self.next() {
+                    // The opening star cannot also close the comment
+                    if c == '/' && text.ends_with('*') {
+                        text.pop();
+                        return Ok(Some(NodeKind::BlockComment(text)));
+                    }
+                    text.push(c);
+                }
+                Err(self.error(MooErrorKind::UnterminatedComment, start, 2))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Fetch the rest of an include directive, right after its `@`
+    fn include(&mut self, start: usize) -> Result<PathBuf, MooError> {
+        let span = Span::new(self.file, start, 1);
+        let malformed = move || MooError::new(MooErrorKind::MalformedInclude, span);
+        for expected in "include".chars() {
+            if self.next() != Some(expected) {
+                return Err(malformed());
+            }
+        }
+        while self.next_if(|c| *c != '\n' && c.is_whitespace()).is_some() {}
+        if self.next() != Some('"') {
+            return Err(malformed());
+        }
+        let mut path: String = String::new();
+        loop {
+            match self.next() {
+                Some('"') => break,
+                Some('\n') | None => return Err(malformed()),
+                Some(c) => path.push(c),
+            }
+        }
+        if self.next() != Some(';') {
+            return Err(malformed());
+        }
+        Ok(PathBuf::from(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, len: usize) -> Span {
+        Span::new(0, start, len)
+    }
+
+    fn kinds(nodes: &[Node]) -> Vec<&NodeKind> {
+        nodes.iter().map(|node| &node.kind).collect()
+    }
+
+    /// Kind and span of the error met parsing `text`
+    fn error(text: &str) -> (MooErrorKind, Span) {
+        let error = parse(text, 0).expect_err(text);
+        (error.kind().clone(), error.span())
+    }
+
+    #[test]
+    fn simple_commands() {
+        let program = parse("+-<>,.^", 0).unwrap();
+        assert_eq!(
+            kinds(&program.nodes),
+            [
+                &NodeKind::Plus,
+                &NodeKind::Minus,
+                &NodeKind::Left,
+                &NodeKind::Right,
+                &NodeKind::In,
+                &NodeKind::Out,
+                &NodeKind::MetaToggle,
+            ]
+        );
+        assert_eq!(program.nodes[3].span, span(3, 1));
+    }
+
+    #[test]
+    fn spans_are_in_bytes() {
+        let program = parse("é+\n", 3).unwrap();
+        assert_eq!(
+            kinds(&program.nodes),
+            [&NodeKind::Text('é'), &NodeKind::Plus, &NodeKind::Text('\n')]
+        );
+        assert_eq!(program.nodes[0].span, Span::new(3, 0, 2));
+        assert_eq!(program.nodes[1].span, Span::new(3, 2, 1));
+    }
+
+    #[test]
+    fn nested_loops() {
+        let program = parse("[+[-]]", 0).unwrap();
+        assert_eq!(program.nodes.len(), 1);
+        let NodeKind::Loop { body, end } = &program.nodes[0].kind else {
+            panic!("not a loop: {:?}", program.nodes[0]);
+        };
+        assert_eq!(program.nodes[0].span, span(0, 1));
+        assert_eq!(*end, span(5, 1));
+        assert_eq!(body[0].kind, NodeKind::Plus);
+        assert_eq!(
+            body[1].kind,
+            NodeKind::Loop {
+                body: vec![Node {
+                    kind: NodeKind::Minus,
+                    span: span(3, 1),
+                }],
+                end: span(4, 1),
+            }
+        );
+    }
+
+    #[test]
+    fn functions_and_calls() {
+        let program = parse("(f):{ + } (!f):{~f;} ~ g ;", 0).unwrap();
+        let nodes: Vec<&Node> = program
+            .nodes
+            .iter()
+            .filter(|node| node.kind != NodeKind::Text(' '))
+            .collect();
+        assert_eq!(
+            nodes[0].kind,
+            NodeKind::Function {
+                name: "f".to_string(),
+                overrides: false,
+                body: vec![
+                    Node {
+                        kind: NodeKind::Text(' '),
+                        span: span(5, 1),
+                    },
+                    Node {
+                        kind: NodeKind::Plus,
+                        span: span(6, 1),
+                    },
+                    Node {
+                        kind: NodeKind::Text(' '),
+                        span: span(7, 1),
+                    },
+                ],
+                end: span(8, 1),
+            }
+        );
+        assert_eq!(nodes[0].span, span(0, 5));
+        assert_eq!(
+            nodes[1].kind,
+            NodeKind::Function {
+                name: "f".to_string(),
+                overrides: true,
+                body: vec![Node {
+                    kind: NodeKind::Call("f".to_string()),
+                    span: span(16, 3),
+                }],
+                end: span(19, 1),
+            }
+        );
+        assert_eq!(nodes[1].span, span(10, 6));
+        assert_eq!(nodes[2].kind, NodeKind::Call("g".to_string()));
+        assert_eq!(nodes[2].span, span(21, 5));
+    }
+
+    #[test]
+    fn includes() {
+        let program = parse("@include \"lib/a.moo\";+", 0).unwrap();
+        assert_eq!(
+            kinds(&program.nodes),
+            [
+                &NodeKind::Include(PathBuf::from("lib/a.moo")),
+                &NodeKind::Plus
+            ]
+        );
+        assert_eq!(program.nodes[0].span, span(0, 21));
+    }
+
+    #[test]
+    fn at_sign_without_include_is_text() {
+        let program = parse("x@y.com", 0).unwrap();
+        assert_eq!(program.nodes[1].kind, NodeKind::Text('@'));
+        assert_eq!(program.nodes[3].kind, NodeKind::Out);
+    }
+
+    #[test]
+    fn comments() {
+        let program = parse("// a [\n/* b\n] */+/", 0).unwrap();
+        assert_eq!(
+            kinds(&program.nodes),
+            [
+                &NodeKind::LineComment(" a [".to_string()),
+                &NodeKind::Text('\n'),
+                &NodeKind::BlockComment(" b\n] ".to_string()),
+                &NodeKind::Plus,
+                &NodeKind::Text('/'),
+            ]
+        );
+        assert_eq!(program.nodes[0].span, span(0, 6));
+        assert_eq!(program.nodes[2].span, span(7, 9));
+    }
+
+    #[test]
+    fn header_errors() {
+        assert_eq!(
+            error("(1f):{}"),
+            (MooErrorKind::InvalidIdentifierStart, span(1, 1))
+        );
+        assert_eq!(
+            error("(f"),
+            (MooErrorKind::MalformedHeader(')'), span(2, 1))
+        );
+        assert_eq!(
+            error("(f)"),
+            (MooErrorKind::MalformedHeader(':'), span(3, 1))
+        );
+        assert_eq!(
+            error("(f):"),
+            (MooErrorKind::MalformedHeader('{'), span(4, 1))
+        );
+        assert_eq!(error("~"), (MooErrorKind::EmptyIdentifier, span(1, 1)));
+        assert_eq!(error("~f+"), (MooErrorKind::MissingSemicolon, span(0, 3)));
+    }
+
+    #[test]
+    fn bracket_errors() {
+        assert_eq!(error("+["), (MooErrorKind::UnmatchedOpenLoop, span(1, 1)));
+        assert_eq!(error("+]"), (MooErrorKind::UnmatchedCloseLoop, span(1, 1)));
+        assert_eq!(
+            error("}"),
+            (MooErrorKind::UnexpectedFunctionEnd, span(0, 1))
+        );
+        assert_eq!(
+            error("[}"),
+            (MooErrorKind::UnexpectedFunctionEnd, span(1, 1))
+        );
+        assert_eq!(
+            error("(f):{ ]"),
+            (MooErrorKind::UnmatchedCloseLoop, span(6, 1))
+        );
+        assert_eq!(
+            error("(f):{ [+"),
+            (MooErrorKind::UnterminatedDefinition, span(0, 5))
+        );
+        assert_eq!(
+            error("(f):{ [ }"),
+            (MooErrorKind::LoopOpenAtFunctionEnd, span(6, 1))
+        );
+        assert_eq!(
+            error("[ (f):{ ] }"),
+            (MooErrorKind::LoopClosedAcrossFunction, span(8, 1))
+        );
+    }
+
+    #[test]
+    fn comment_and_include_errors() {
+        assert_eq!(
+            error("+/* never closed"),
+            (MooErrorKind::UnterminatedComment, span(1, 2))
+        );
+        assert_eq!(
+            error("/*/"),
+            (MooErrorKind::UnterminatedComment, span(0, 2))
+        );
+        assert_eq!(
+            error("@include a.moo;"),
+            (MooErrorKind::MalformedInclude, span(0, 1))
+        );
+        assert_eq!(
+            error("@include \"a.moo\""),
+            (MooErrorKind::MalformedInclude, span(0, 1))
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Values held in the cells of the ribbons
+//!
+//! [`Runner`](super::Runner) works on any [`Cell`], which is implemented for
+//! unsigned and signed integers of 8, 16 and 32 bits.
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+
+/// An integer a cell can hold
+pub trait Cell: Copy + Default + Eq + Ord + Hash + Debug + Display {
+    /// Largest value a cell can hold
+    const MAX: Self;
+    /// Number of characters needed to display any value, sign included
+    const WIDTH: usize;
+
+    /// Add one, going back to the smallest value past the largest one
+    #[must_use]
+    fn wrapping_increment(self) -> Self;
+
+    /// Remove one, going back to the largest value past the smallest one
+    #[must_use]
+    fn wrapping_decrement(self) -> Self;
+
+    /// Add one, unless the value is the largest one
+    fn checked_increment(self) -> Option<Self>;
+
+    /// Remove one, unless the value is the smallest one
+    fn checked_decrement(self) -> Option<Self>;
+
+    /// Value of a byte read from the input
+    fn from_byte(byte: u8) -> Self;
+
+    /// Byte written to the output, which is the lowest byte of the value
+    fn to_byte(self) -> u8;
+
+    /// Bytes of the value, lowest first
+    fn to_bytes(self) -> Vec<u8>;
+}
+
+macro_rules! impl_cell {
+    ($($t:ty => $width:expr),* $(,)?) => {
+        $(
+            impl Cell for $t {
+                const MAX: Self = <$t>::MAX;
+                const WIDTH: usize = $width;
+
+                fn wrapping_increment(self) -> Self {
+                    self.wrapping_add(1)
+                }
+
+                fn wrapping_decrement(self) -> Self {
+                    self.wrapping_sub(1)
+                }
+
+                fn checked_increment(self) -> Option<Self> {
+                    self.checked_add(1)
+                }
+
+                fn checked_decrement(self) -> Option<Self> {
+                    self.checked_sub(1)
+                }
+
+                #[allow(clippy::cast_possible_wrap, clippy::cast_lossless)]
+                fn from_byte(byte: u8) -> Self {
+                    byte as Self
+                }
+
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                fn to_byte(self) -> u8 {
+                    self as u8
+                }
+
+                fn to_bytes(self) -> Vec<u8> {
+                    self.to_le_bytes().to_vec()
+                }
+            }
+        )*
+    };
+}
+
+impl_cell! {
+    u8 => 3,
+    u16 => 5,
+    u32 => 10,
+    i8 => 4,
+    i16 => 6,
+    i32 => 11,
+}